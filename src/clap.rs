@@ -0,0 +1,925 @@
+//! CLAP entry point around `FreezeEngine`, built with the `clap` feature.
+//!
+//! Only the parts of the CLAP ABI the plugin uses are declared here, laid
+//! out after the 1.x headers, so no bindings crate is needed. Many of their
+//! fields are there for the layout alone.
+
+use std::{
+    cell::UnsafeCell,
+    ffi::{c_char, c_void, CStr},
+    ptr, slice,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    bus_sample,
+    engine::{FreezeConfig, FreezeEngine},
+    Parameter, ZamerzikaParameters, CHANNELS,
+};
+
+// Like the VST name and vendor, a customized build can set its own CLAP
+// id, which hosts key plugins and their saved state by.
+const ID: &str = match option_env!("ZAMERZIKA_CLAP_ID") {
+    Some(id) => id,
+    None => "me.prakapchuk.zamerzika",
+};
+const ID_BYTES: [u8; ID.len() + 1] = nul_terminated(ID);
+const NAME_BYTES: [u8; crate::NAME.len() + 1] = nul_terminated(crate::NAME);
+const VENDOR_BYTES: [u8; crate::VENDOR.len() + 1] = nul_terminated(crate::VENDOR);
+const PLUGIN_ID: &CStr = c_str(&ID_BYTES);
+const NAME: &CStr = c_str(&NAME_BYTES);
+const VENDOR: &CStr = c_str(&VENDOR_BYTES);
+const VERSION: &CStr = c_str(concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes());
+
+/// `text` with a nul appended, `N` being one more than its length.
+const fn nul_terminated<const N: usize>(text: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+    let mut i = 0;
+    while i < text.len() {
+        bytes[i] = text.as_bytes()[i];
+        i += 1;
+    }
+    bytes
+}
+
+/// Compile time check that `bytes` end in their only nul.
+const fn c_str(bytes: &[u8]) -> &CStr {
+    match CStr::from_bytes_with_nul(bytes) {
+        Ok(text) => text,
+        Err(_) => panic!("descriptor strings can't contain a nul"),
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+const CLAP_VERSION: ClapVersion = ClapVersion {
+    major: 1,
+    minor: 2,
+    revision: 0,
+};
+
+#[repr(C)]
+pub struct ClapPluginEntry {
+    clap_version: ClapVersion,
+    init: extern "C" fn(plugin_path: *const c_char) -> bool,
+    deinit: extern "C" fn(),
+    get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+}
+
+/// Looked up by hosts when loading the library as a CLAP plugin.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static clap_entry: ClapPluginEntry = ClapPluginEntry {
+    clap_version: CLAP_VERSION,
+    init: entry_init,
+    deinit: entry_deinit,
+    get_factory: entry_get_factory,
+};
+
+extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+extern "C" fn entry_deinit() {}
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if CStr::from_ptr(factory_id) == c"clap.plugin-factory" {
+        &FACTORY as *const ClapPluginFactory as _
+    } else {
+        ptr::null()
+    }
+}
+
+#[repr(C)]
+struct ClapPluginFactory {
+    get_plugin_count: unsafe extern "C" fn(factory: *const ClapPluginFactory) -> u32,
+    get_plugin_descriptor:
+        unsafe extern "C" fn(factory: *const ClapPluginFactory, index: u32) -> *const c_void,
+    create_plugin: unsafe extern "C" fn(
+        factory: *const ClapPluginFactory,
+        host: *const c_void,
+        plugin_id: *const c_char,
+    ) -> *const ClapPlugin,
+}
+
+static FACTORY: ClapPluginFactory = ClapPluginFactory {
+    get_plugin_count: factory_get_plugin_count,
+    get_plugin_descriptor: factory_get_plugin_descriptor,
+    create_plugin: factory_create_plugin,
+};
+
+unsafe extern "C" fn factory_get_plugin_count(_factory: *const ClapPluginFactory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_get_plugin_descriptor(
+    _factory: *const ClapPluginFactory,
+    index: u32,
+) -> *const c_void {
+    if index == 0 {
+        &DESCRIPTOR.0 as *const ClapPluginDescriptor as _
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_create_plugin(
+    _factory: *const ClapPluginFactory,
+    _host: *const c_void,
+    plugin_id: *const c_char,
+) -> *const ClapPlugin {
+    if CStr::from_ptr(plugin_id) != PLUGIN_ID {
+        return ptr::null();
+    }
+    let params = Arc::new(ZamerzikaParameters::default());
+    let mut instance = Box::new(Instance {
+        plugin: ClapPlugin {
+            desc: &DESCRIPTOR.0,
+            plugin_data: ptr::null_mut(),
+            init: plugin_init,
+            destroy: plugin_destroy,
+            activate: plugin_activate,
+            deactivate: plugin_deactivate,
+            start_processing: plugin_start_processing,
+            stop_processing: plugin_stop_processing,
+            reset: plugin_reset,
+            process: plugin_process,
+            get_extension: plugin_get_extension,
+            on_main_thread: plugin_on_main_thread,
+        },
+        engine: UnsafeCell::new(FreezeEngine::new(
+            Arc::clone(&params),
+            CHANNELS,
+            FreezeConfig::default(),
+        )),
+        params,
+        tail: AtomicU32::new(0),
+    });
+    instance.plugin.plugin_data = &mut *instance as *mut Instance as _;
+    &Box::leak(instance).plugin
+}
+
+/// Wrapper for statics holding pointers which are never written through.
+struct Shared<T>(T);
+
+unsafe impl<T> Sync for Shared<T> {}
+
+#[repr(C)]
+struct ClapPluginDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    features: *const *const c_char,
+}
+
+static FEATURES: Shared<[*const c_char; 3]> =
+    Shared([c"audio-effect".as_ptr(), c"stereo".as_ptr(), ptr::null()]);
+
+static DESCRIPTOR: Shared<ClapPluginDescriptor> = Shared(ClapPluginDescriptor {
+    clap_version: CLAP_VERSION,
+    id: PLUGIN_ID.as_ptr(),
+    name: NAME.as_ptr(),
+    vendor: VENDOR.as_ptr(),
+    url: c"".as_ptr(),
+    manual_url: c"".as_ptr(),
+    support_url: c"".as_ptr(),
+    version: VERSION.as_ptr(),
+    description: c"Freezes the input at the pitch of the held notes".as_ptr(),
+    features: &FEATURES.0 as *const [*const c_char; 3] as _,
+});
+
+#[repr(C)]
+struct ClapPlugin {
+    desc: *const ClapPluginDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    destroy: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    activate: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        sample_rate: f64,
+        min_frames: u32,
+        max_frames: u32,
+    ) -> bool,
+    deactivate: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    start_processing: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    stop_processing: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    reset: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    process: unsafe extern "C" fn(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32,
+    get_extension:
+        unsafe extern "C" fn(plugin: *const ClapPlugin, id: *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(plugin: *const ClapPlugin),
+}
+
+/// A plugin instance, owning the `ClapPlugin` handed to the host.
+///
+/// Callbacks come from the main and the audio thread at once, so they
+/// only share it by reference: the main thread sticks to the parameters,
+/// and the engine is left to the audio thread, see `engine`.
+struct Instance {
+    plugin: ClapPlugin,
+    params: Arc<ZamerzikaParameters>,
+    engine: UnsafeCell<FreezeEngine>,
+    /// Tail length in frames as of the latest block, for the main thread.
+    tail: AtomicU32,
+}
+
+impl Instance {
+    /// Apply an incoming event, translating notes to the MIDI messages the
+    /// engine understands, and echo it to `out_events` if it's a note MIDI
+    /// thru passes on.
+    unsafe fn handle_event(
+        &self,
+        engine: &mut FreezeEngine,
+        header: *const ClapEventHeader,
+        out_events: Option<&ClapOutputEvents>,
+    ) {
+        if (*header).space_id != CLAP_CORE_EVENT_SPACE_ID {
+            return;
+        }
+        let data = match (*header).type_ {
+            CLAP_EVENT_NOTE_ON | CLAP_EVENT_NOTE_OFF => {
+                let note = &*(header as *const ClapEventNote);
+                let Ok(key) = u8::try_from(note.key) else {
+                    return;
+                };
+                let channel = u8::try_from(note.channel).unwrap_or(0) & 0x0F;
+                if (*header).type_ == CLAP_EVENT_NOTE_ON {
+                    // Zero would read as a note-off.
+                    let velocity = (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+                    [0x90 | channel, key & 0x7F, velocity]
+                } else {
+                    let velocity = (note.velocity * 127.0).round().clamp(0.0, 127.0) as u8;
+                    [0x80 | channel, key & 0x7F, velocity]
+                }
+            }
+            CLAP_EVENT_MIDI => (*(header as *const ClapEventMidi)).data,
+            CLAP_EVENT_PARAM_VALUE => return self.handle_param(header),
+            _ => return,
+        };
+        engine.process_midi(data);
+        if let Some(out_events) = out_events.filter(|_| self.params.is_thru_note(data)) {
+            // As it came in, in whichever dialect, so the original channel
+            // and velocity carry on. There's nothing to do if the host's
+            // queue is full but drop it.
+            (out_events.try_push)(out_events, header);
+        }
+    }
+
+    unsafe fn handle_param(&self, header: *const ClapEventHeader) {
+        if (*header).space_id == CLAP_CORE_EVENT_SPACE_ID
+            && (*header).type_ == CLAP_EVENT_PARAM_VALUE
+        {
+            let event = &*(header as *const ClapEventParamValue);
+            if let Some(parameter) = Parameter::from_index(event.param_id as i32) {
+                self.params
+                    .set(parameter, from_clap(parameter, event.value));
+            }
+        }
+    }
+
+    /// Publish the tail `engine` has now for the main thread to read.
+    fn update_tail(&self, engine: &FreezeEngine) {
+        let tail = engine.tail_frames().min(u32::MAX as usize - 1) as u32;
+        self.tail.store(tail, Ordering::Relaxed);
+    }
+}
+
+unsafe fn instance<'a>(plugin: *const ClapPlugin) -> &'a Instance {
+    &*((*plugin).plugin_data as *const Instance)
+}
+
+/// Engine of the instance, only for the callbacks CLAP makes on the audio
+/// thread, or on the main thread while the plugin is deactivated, which
+/// never run at the same time.
+unsafe fn engine<'a>(plugin: *const ClapPlugin) -> &'a mut FreezeEngine {
+    &mut *instance(plugin).engine.get()
+}
+
+unsafe extern "C" fn plugin_init(_plugin: *const ClapPlugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const ClapPlugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut Instance));
+}
+
+unsafe extern "C" fn plugin_activate(
+    plugin: *const ClapPlugin,
+    sample_rate: f64,
+    _min_frames: u32,
+    _max_frames: u32,
+) -> bool {
+    let engine = engine(plugin);
+    engine.set_sample_rate(sample_rate);
+    instance(plugin).update_tail(engine);
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(_plugin: *const ClapPlugin) {}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const ClapPlugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin: *const ClapPlugin) {}
+
+unsafe extern "C" fn plugin_reset(plugin: *const ClapPlugin) {
+    instance(plugin).params.apply_staged();
+    engine(plugin).reset();
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const ClapPlugin) {}
+
+const CLAP_PROCESS_CONTINUE: i32 = 1;
+
+#[repr(C)]
+struct ClapProcess {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const ClapEventTransport,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const ClapInputEvents,
+    out_events: *const ClapOutputEvents,
+}
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+impl ClapAudioBuffer {
+    unsafe fn sample(&self, channel: usize, frame: usize) -> f64 {
+        if self.data64.is_null() {
+            f64::from(*(*self.data32.add(channel)).add(frame))
+        } else {
+            *(*self.data64.add(channel)).add(frame)
+        }
+    }
+
+    unsafe fn set_sample(&self, channel: usize, frame: usize, sample: f64) {
+        if self.data64.is_null() {
+            *(*self.data32.add(channel)).add(frame) = sample as f32;
+        } else {
+            *(*self.data64.add(channel)).add(frame) = sample;
+        }
+    }
+}
+
+#[repr(C)]
+struct ClapInputEvents {
+    ctx: *mut c_void,
+    size: unsafe extern "C" fn(list: *const ClapInputEvents) -> u32,
+    get: unsafe extern "C" fn(list: *const ClapInputEvents, index: u32) -> *const ClapEventHeader,
+}
+
+#[repr(C)]
+struct ClapOutputEvents {
+    ctx: *mut c_void,
+    try_push:
+        unsafe extern "C" fn(list: *const ClapOutputEvents, event: *const ClapEventHeader) -> bool,
+}
+
+const CLAP_CORE_EVENT_SPACE_ID: u16 = 0;
+const CLAP_EVENT_NOTE_ON: u16 = 0;
+const CLAP_EVENT_NOTE_OFF: u16 = 1;
+const CLAP_EVENT_PARAM_VALUE: u16 = 5;
+const CLAP_EVENT_MIDI: u16 = 10;
+
+#[repr(C)]
+struct ClapEventHeader {
+    size: u32,
+    /// Frame offset within the block.
+    time: u32,
+    space_id: u16,
+    type_: u16,
+    flags: u32,
+}
+
+#[repr(C)]
+struct ClapEventNote {
+    header: ClapEventHeader,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    velocity: f64,
+}
+
+#[repr(C)]
+struct ClapEventMidi {
+    header: ClapEventHeader,
+    port_index: u16,
+    data: [u8; 3],
+}
+
+#[repr(C)]
+struct ClapEventParamValue {
+    header: ClapEventHeader,
+    param_id: u32,
+    cookie: *mut c_void,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    value: f64,
+}
+
+const CLAP_TRANSPORT_HAS_TEMPO: u32 = 1 << 0;
+const CLAP_TRANSPORT_IS_PLAYING: u32 = 1 << 4;
+
+#[repr(C)]
+struct ClapEventTransport {
+    header: ClapEventHeader,
+    flags: u32,
+    song_pos_beats: i64,
+    song_pos_seconds: i64,
+    tempo: f64,
+    tempo_inc: f64,
+    loop_start_beats: i64,
+    loop_end_beats: i64,
+    loop_start_seconds: i64,
+    loop_end_seconds: i64,
+    bar_start: i64,
+    bar_number: i32,
+    tsig_num: u16,
+    tsig_denom: u16,
+}
+
+unsafe extern "C" fn plugin_process(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32 {
+    let (instance, engine) = (instance(plugin), engine(plugin));
+    instance.params.apply_staged();
+    let process = &*process;
+    let tempo = process
+        .transport
+        .as_ref()
+        .filter(|transport| transport.flags & CLAP_TRANSPORT_HAS_TEMPO != 0)
+        .map(|transport| transport.tempo)
+        .filter(|&tempo| tempo > 0.0);
+    engine.set_tempo(tempo);
+    if let Some(transport) = process.transport.as_ref() {
+        engine.set_playing(transport.flags & CLAP_TRANSPORT_IS_PLAYING != 0);
+    }
+    let events = &*process.in_events;
+    let out_events = process.out_events.as_ref();
+    let event_count = (events.size)(events);
+    let mut next_event = 0;
+    let inputs = slice_or_empty(process.audio_inputs, process.audio_inputs_count);
+    let outputs = slice_or_empty(process.audio_outputs, process.audio_outputs_count);
+    let (Some(input), Some(output)) = (inputs.first(), outputs.first()) else {
+        while next_event < event_count {
+            instance.handle_event(engine, (events.get)(events, next_event), out_events);
+            next_event += 1;
+        }
+        return CLAP_PROCESS_CONTINUE;
+    };
+    let channels = (output.channel_count as usize).min(engine.channels());
+    // Same as the VST side: the sidechain when asked for and connected,
+    // the main input otherwise.
+    let source = inputs
+        .get(1)
+        .filter(|sidechain| {
+            instance.params.switch(Parameter::Sidechain)
+                && sidechain.channel_count as usize >= channels
+        })
+        .unwrap_or(input);
+    let mut scratch = [0.0; CHANNELS];
+    let mut sidechain = [0.0; CHANNELS];
+    let mut peak = 0.0f64;
+    for frame in 0..process.frames_count as usize {
+        while next_event < event_count {
+            let header = (events.get)(events, next_event);
+            if (*header).time as usize > frame {
+                break;
+            }
+            instance.handle_event(engine, header, out_events);
+            next_event += 1;
+        }
+        let frame_samples = &mut scratch[..channels];
+        let sidechain = &mut sidechain[..channels];
+        for channel in 0..channels {
+            frame_samples[channel] = bus_sample(
+                input.channel_count as usize,
+                channels,
+                channel,
+                |input_channel| input.sample(input_channel, frame),
+            );
+            sidechain[channel] = bus_sample(
+                source.channel_count as usize,
+                channels,
+                channel,
+                |input_channel| source.sample(input_channel, frame),
+            );
+        }
+        engine.process_sidechain_frame(frame_samples, sidechain);
+        for (channel, &sample) in frame_samples.iter().enumerate() {
+            output.set_sample(channel, frame, sample);
+            peak = peak.max(sample.abs());
+        }
+        for channel in channels..output.channel_count as usize {
+            output.set_sample(channel, frame, 0.0);
+        }
+    }
+    while next_event < event_count {
+        instance.handle_event(engine, (events.get)(events, next_event), out_events);
+        next_event += 1;
+    }
+    instance.params.meters().update(peak, engine.is_frozen());
+    instance.update_tail(engine);
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe fn slice_or_empty<'a, T>(data: *const T, len: u32) -> &'a [T] {
+    if data.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len as usize)
+    }
+}
+
+unsafe extern "C" fn plugin_get_extension(
+    _plugin: *const ClapPlugin,
+    id: *const c_char,
+) -> *const c_void {
+    match CStr::from_ptr(id).to_bytes() {
+        b"clap.params" => &PARAMS as *const ClapPluginParams as _,
+        b"clap.audio-ports" => &AUDIO_PORTS as *const ClapPluginAudioPorts as _,
+        b"clap.note-ports" => &NOTE_PORTS as *const ClapPluginNotePorts as _,
+        b"clap.state" => &STATE as *const ClapPluginState as _,
+        b"clap.tail" => &TAIL as *const ClapPluginTail as _,
+        _ => ptr::null(),
+    }
+}
+
+/// Copy `text` into the C string buffer `dest`, truncating it to fit.
+fn write_c_str(dest: &mut [c_char], text: &str) {
+    let Some(room) = dest.len().checked_sub(1) else {
+        return;
+    };
+    let len = text.len().min(room);
+    for (dest, &byte) in dest.iter_mut().zip(&text.as_bytes()[..len]) {
+        *dest = byte as c_char;
+    }
+    dest[len] = 0;
+}
+
+const CLAP_PARAM_IS_STEPPED: u32 = 1 << 0;
+const CLAP_PARAM_IS_AUTOMATABLE: u32 = 1 << 5;
+
+/// CLAP value of `parameter` at the normalized `value`: the step number
+/// for stepped parameters, which CLAP counts in whole numbers, and the
+/// normalized value itself for the rest.
+fn to_clap(parameter: Parameter, value: f32) -> f64 {
+    match parameter.steps() {
+        Some(steps) => f64::from(value) * (steps - 1) as f64,
+        None => f64::from(value),
+    }
+}
+
+/// Normalized value of `parameter` at the CLAP `value`, see `to_clap`.
+fn from_clap(parameter: Parameter, value: f64) -> f32 {
+    match parameter.steps() {
+        Some(steps) => (value.round() / (steps - 1) as f64) as f32,
+        None => value as f32,
+    }
+}
+
+#[repr(C)]
+struct ClapParamInfo {
+    id: u32,
+    flags: u32,
+    cookie: *mut c_void,
+    name: [c_char; 256],
+    module: [c_char; 1024],
+    min_value: f64,
+    max_value: f64,
+    default_value: f64,
+}
+
+#[repr(C)]
+struct ClapPluginParams {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin) -> u32,
+    get_info: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        index: u32,
+        info: *mut ClapParamInfo,
+    ) -> bool,
+    get_value: unsafe extern "C" fn(plugin: *const ClapPlugin, id: u32, value: *mut f64) -> bool,
+    value_to_text: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        id: u32,
+        value: f64,
+        display: *mut c_char,
+        size: u32,
+    ) -> bool,
+    text_to_value: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        id: u32,
+        display: *const c_char,
+        value: *mut f64,
+    ) -> bool,
+    flush: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        in_events: *const ClapInputEvents,
+        out_events: *const c_void,
+    ),
+}
+
+/// Parameters keep the normalized range of the VST side but for stepped
+/// ones, which count their steps, so automation and presets mean the same
+/// in both.
+static PARAMS: ClapPluginParams = ClapPluginParams {
+    count: params_count,
+    get_info: params_get_info,
+    get_value: params_get_value,
+    value_to_text: params_value_to_text,
+    text_to_value: params_text_to_value,
+    flush: params_flush,
+};
+
+unsafe extern "C" fn params_count(_plugin: *const ClapPlugin) -> u32 {
+    Parameter::COUNT as u32
+}
+
+unsafe extern "C" fn params_get_info(
+    _plugin: *const ClapPlugin,
+    index: u32,
+    info: *mut ClapParamInfo,
+) -> bool {
+    let Some(parameter) = Parameter::from_index(index as i32) else {
+        return false;
+    };
+    let info = &mut *info;
+    info.id = index;
+    info.flags = match parameter.steps() {
+        Some(_) => CLAP_PARAM_IS_AUTOMATABLE | CLAP_PARAM_IS_STEPPED,
+        None => CLAP_PARAM_IS_AUTOMATABLE,
+    };
+    info.cookie = ptr::null_mut();
+    write_c_str(&mut info.name, parameter.name());
+    write_c_str(&mut info.module, "");
+    info.min_value = 0.0;
+    info.max_value = to_clap(parameter, 1.0);
+    info.default_value = to_clap(parameter, parameter.default_value());
+    true
+}
+
+unsafe extern "C" fn params_get_value(plugin: *const ClapPlugin, id: u32, value: *mut f64) -> bool {
+    let Some(parameter) = Parameter::from_index(id as i32) else {
+        return false;
+    };
+    *value = to_clap(parameter, instance(plugin).params.value(parameter));
+    true
+}
+
+unsafe extern "C" fn params_value_to_text(
+    _plugin: *const ClapPlugin,
+    id: u32,
+    value: f64,
+    display: *mut c_char,
+    size: u32,
+) -> bool {
+    let Some(parameter) = Parameter::from_index(id as i32) else {
+        return false;
+    };
+    let display = slice::from_raw_parts_mut(display, size as usize);
+    write_c_str(display, &parameter.display(from_clap(parameter, value)));
+    true
+}
+
+unsafe extern "C" fn params_text_to_value(
+    _plugin: *const ClapPlugin,
+    id: u32,
+    display: *const c_char,
+    value: *mut f64,
+) -> bool {
+    let Some(parameter) = Parameter::from_index(id as i32) else {
+        return false;
+    };
+    let Ok(display) = CStr::from_ptr(display).to_str() else {
+        return false;
+    };
+    match parameter.parse(display) {
+        Some(parsed) => {
+            *value = to_clap(parameter, parsed);
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_flush(
+    plugin: *const ClapPlugin,
+    in_events: *const ClapInputEvents,
+    _out_events: *const c_void,
+) {
+    let instance = instance(plugin);
+    let events = &*in_events;
+    for index in 0..(events.size)(events) {
+        instance.handle_param((events.get)(events, index));
+    }
+}
+
+const CLAP_AUDIO_PORT_IS_MAIN: u32 = 1 << 0;
+const CLAP_AUDIO_PORT_SUPPORTS_64BITS: u32 = 1 << 1;
+const CLAP_INVALID_ID: u32 = u32::MAX;
+
+#[repr(C)]
+struct ClapAudioPortInfo {
+    id: u32,
+    name: [c_char; 256],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+#[repr(C)]
+struct ClapPluginAudioPorts {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        index: u32,
+        is_input: bool,
+        info: *mut ClapAudioPortInfo,
+    ) -> bool,
+}
+
+/// A main stereo port either way, plus the sidechain input.
+static AUDIO_PORTS: ClapPluginAudioPorts = ClapPluginAudioPorts {
+    count: audio_ports_count,
+    get: audio_ports_get,
+};
+
+unsafe extern "C" fn audio_ports_count(_plugin: *const ClapPlugin, is_input: bool) -> u32 {
+    if is_input {
+        2
+    } else {
+        1
+    }
+}
+
+unsafe extern "C" fn audio_ports_get(
+    _plugin: *const ClapPlugin,
+    index: u32,
+    is_input: bool,
+    info: *mut ClapAudioPortInfo,
+) -> bool {
+    let (name, flags) = match (index, is_input) {
+        (0, _) => ("Main", CLAP_AUDIO_PORT_IS_MAIN),
+        (1, true) => ("Sidechain", 0),
+        _ => return false,
+    };
+    let info = &mut *info;
+    info.id = index;
+    write_c_str(&mut info.name, name);
+    info.flags = flags | CLAP_AUDIO_PORT_SUPPORTS_64BITS;
+    info.channel_count = CHANNELS as u32;
+    info.port_type = c"stereo".as_ptr();
+    info.in_place_pair = CLAP_INVALID_ID;
+    true
+}
+
+const CLAP_NOTE_DIALECT_CLAP: u32 = 1 << 0;
+const CLAP_NOTE_DIALECT_MIDI: u32 = 1 << 1;
+
+#[repr(C)]
+struct ClapNotePortInfo {
+    id: u32,
+    supported_dialects: u32,
+    preferred_dialect: u32,
+    name: [c_char; 256],
+}
+
+#[repr(C)]
+struct ClapPluginNotePorts {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(
+        plugin: *const ClapPlugin,
+        index: u32,
+        is_input: bool,
+        info: *mut ClapNotePortInfo,
+    ) -> bool,
+}
+
+/// A single input, preferring MIDI so controllers, aftertouch and bends
+/// all arrive the way the engine handles them, and an output for MIDI
+/// thru.
+static NOTE_PORTS: ClapPluginNotePorts = ClapPluginNotePorts {
+    count: note_ports_count,
+    get: note_ports_get,
+};
+
+unsafe extern "C" fn note_ports_count(_plugin: *const ClapPlugin, _is_input: bool) -> u32 {
+    1
+}
+
+unsafe extern "C" fn note_ports_get(
+    _plugin: *const ClapPlugin,
+    index: u32,
+    is_input: bool,
+    info: *mut ClapNotePortInfo,
+) -> bool {
+    if index != 0 {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    info.supported_dialects = CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI;
+    info.preferred_dialect = CLAP_NOTE_DIALECT_MIDI;
+    write_c_str(&mut info.name, if is_input { "MIDI" } else { "MIDI thru" });
+    true
+}
+
+#[repr(C)]
+struct ClapOutputStream {
+    ctx: *mut c_void,
+    write: unsafe extern "C" fn(
+        stream: *const ClapOutputStream,
+        buffer: *const c_void,
+        size: u64,
+    ) -> i64,
+}
+
+#[repr(C)]
+struct ClapInputStream {
+    ctx: *mut c_void,
+    read:
+        unsafe extern "C" fn(stream: *const ClapInputStream, buffer: *mut c_void, size: u64) -> i64,
+}
+
+#[repr(C)]
+struct ClapPluginState {
+    save: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapOutputStream) -> bool,
+    load: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapInputStream) -> bool,
+}
+
+/// State is the VST preset chunk, so presets move freely between the two.
+static STATE: ClapPluginState = ClapPluginState {
+    save: state_save,
+    load: state_load,
+};
+
+unsafe extern "C" fn state_save(
+    plugin: *const ClapPlugin,
+    stream: *const ClapOutputStream,
+) -> bool {
+    let chunk = instance(plugin).params.to_chunk();
+    let mut written = 0;
+    while written < chunk.len() {
+        let rest = &chunk[written..];
+        let size = ((*stream).write)(stream, rest.as_ptr() as _, rest.len() as u64);
+        if size <= 0 {
+            return false;
+        }
+        written += size as usize;
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin: *const ClapPlugin, stream: *const ClapInputStream) -> bool {
+    let mut chunk = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let size = ((*stream).read)(stream, buffer.as_mut_ptr() as _, buffer.len() as u64);
+        match size {
+            0 => break,
+            size if size < 0 => return false,
+            size => chunk.extend_from_slice(&buffer[..size as usize]),
+        }
+    }
+    instance(plugin).params.load_chunk(&chunk);
+    true
+}
+
+#[repr(C)]
+struct ClapPluginTail {
+    get: unsafe extern "C" fn(plugin: *const ClapPlugin) -> u32,
+}
+
+static TAIL: ClapPluginTail = ClapPluginTail { get: tail_get };
+
+unsafe extern "C" fn tail_get(plugin: *const ClapPlugin) -> u32 {
+    instance(plugin).tail.load(Ordering::Relaxed)
+}
@@ -0,0 +1,2773 @@
+//! Host-agnostic freeze engine: voices, capture, and the per-frame DSP.
+
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4, PI, SQRT_2, TAU},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    ring::{wrap_phase, Interpolation, PlayDirection, RingBuffer},
+    Parameter, ZamerzikaParameters, CHANNELS, XFADE_FRAMES,
+};
+
+/// Lowest frequency a window holds a whole period of, just below MIDI note 0
+/// at ~8.176 Hz.
+const MIN_FREQ: f64 = 8.0;
+/// Sample rate the buffers are sized for up front by default, so that hosts
+/// running at it or below never make them reallocate.
+const PREALLOCATED_RATE: f64 = 96_000.0;
+/// Time constant of the speed and octave knob and modwheel smoothing.
+const SPEED_SMOOTH_MS: f64 = 20.0;
+/// Time constant of the smoothing of the other continuous parameters.
+const PARAM_SMOOTH_MS: f64 = 5.0;
+/// Time constants of the input level follower, rising and falling.
+const FOLLOWER_ATTACK_MS: f64 = 1.0;
+const FOLLOWER_RELEASE_MS: f64 = 50.0;
+/// Time constant of the input loudness measurement for snapping to content.
+const CONTENT_RMS_MS: f64 = 10.0;
+/// Time constant of the stutter gate, fast enough to chop without clicks.
+const STUTTER_SMOOTH_MS: f64 = 2.0;
+/// Length of the fade in of a loop the stutter starts over.
+const STUTTER_RAMP_MS: f64 = 2.0;
+/// Time constant of the input loudness the frozen level is matched to.
+const LOUDNESS_MS: f64 = 300.0;
+/// Furthest level matching boosts or cuts a loop, 12 dB.
+const MAX_MATCH_GAIN: f64 = 4.0;
+/// Mean square input level below which it counts as silent, -60 dB.
+const SILENCE_POWER: f64 = 1e-6;
+/// Taps of the oversampling decimation filter per oversampled sample of a
+/// frame.
+const DECIMATOR_TAPS_PER_FACTOR: usize = 8;
+/// Highest oversampling factor of the loop reads.
+const MAX_OVERSAMPLING: usize = 4;
+/// Most grains playing at once in a single voice.
+const MAX_GRAINS: usize = 32;
+/// Initial state of the random generator.
+const DEFAULT_SEED: u64 = 0x5A4D_525A_494B_4131;
+/// Pole of the frozen signal's DC blocker, its cutoff being around 35 Hz at 44.1 kHz.
+const DC_BLOCK_POLE: f64 = 0.995;
+/// Number of notes which can be frozen at the same time.
+const MAX_VOICES: usize = 8;
+/// Number of distinct MIDI keys.
+const MIDI_NOTES: usize = 128;
+/// Octaves the modwheel closes the filter by at full deflection.
+const MOD_CUTOFF_OCTAVES: f64 = 6.0;
+/// Modulation wheel controller number.
+const CC_MODWHEEL: u8 = 1;
+/// Note-off velocity MIDI prescribes for keyboards which don't sense it.
+const DEFAULT_RELEASE_VELOCITY: u8 = 64;
+/// Sustain (damper) pedal controller number.
+const CC_SUSTAIN: u8 = 64;
+/// Channel mode message silencing everything at once.
+const CC_ALL_SOUND_OFF: u8 = 120;
+/// Channel mode message releasing every note.
+const CC_ALL_NOTES_OFF: u8 = 123;
+/// General purpose controller 5, releasing every stacked layer.
+const CC_CLEAR_LAYERS: u8 = 80;
+
+/// Process `input` offline with the settings in `params`, applying the MIDI
+/// channel messages in `events` at their frame offsets. Without a host,
+/// `ZamerzikaParameters::set` is what changes the settings.
+///
+/// Events have to be in order of their offsets; any past the end of the
+/// input are ignored.
+pub fn render(
+    params: Arc<ZamerzikaParameters>,
+    input: &[[f64; CHANNELS]],
+    events: &[(usize, [u8; 3])],
+    sample_rate: f64,
+) -> Vec<[f64; CHANNELS]> {
+    let mut engine = FreezeEngine::new(params, CHANNELS, FreezeConfig::default());
+    engine.set_sample_rate(sample_rate);
+    let mut events = events.iter().peekable();
+    input
+        .iter()
+        .enumerate()
+        .map(|(frame, samples)| {
+            while let Some((_, data)) = events.next_if(|(offset, _)| *offset <= frame) {
+                engine.process_midi(*data);
+            }
+            let mut samples = *samples;
+            engine.process_frame(&mut samples);
+            samples
+        })
+        .collect()
+}
+
+/// The freezing itself, fed MIDI messages and audio frames.
+pub struct FreezeEngine {
+    params: Arc<ZamerzikaParameters>,
+    sample_rate: f64,
+    /// Width of the input and output buses.
+    channels: usize,
+    /// Longest window the buffers hold, see `max_window_size`.
+    max_window: usize,
+    /// See `FreezeConfig::xfade_frames`.
+    xfade_frames: usize,
+    input: Vec<RingBuffer>,
+    /// Recent frozen output per channel, before the mix, for re-freezing.
+    refreeze: Vec<RingBuffer>,
+    voices: Vec<Voice>,
+    /// Current pitch-bend wheel deflection, from -1 to 1, applied to the
+    /// loop playback rate.
+    bend: f64,
+    /// Latest channel aftertouch, from 0 to 1.
+    channel_pressure: f64,
+    /// Playback speed factor, following the speed knob smoothly.
+    speed: Smoother,
+    /// Octaves the loops are transposed by, gliding between the steps of
+    /// the octave knob rather than jumping.
+    octave: Smoother,
+    /// Latest modwheel position, from 0 to 1.
+    mod_wheel: f64,
+    /// Modwheel position following `mod_wheel` smoothly.
+    modulation: Smoother,
+    /// Continuous parameters following their knobs smoothly.
+    controls: Controls,
+    /// Tempo in beats per minute, if known.
+    tempo: Option<f64>,
+    /// Whether the host transport was playing as of the last report.
+    playing: bool,
+    /// Whether the sustain pedal is down, deferring note-offs until it's up.
+    sustain_held: bool,
+    /// A latch mode freeze is playing until the next note-on.
+    latched: bool,
+    /// Hold was on last frame, so switching it off lets go of the freezes.
+    holding: bool,
+    /// Solo was on last frame, so switching it on brings back the last loop.
+    soloing: bool,
+    /// Input level follower, for the trigger mode.
+    input_level: f64,
+    /// Level of the live input followed at the envelope parameters' pace,
+    /// for opening the filter.
+    envelope: f64,
+    /// Running mean square of the input, for snapping to content.
+    input_power: f64,
+    /// Position in the stutter cycle, from 0 to 1.
+    stutter_phase: f64,
+    /// Stutter gate level following its target smoothly.
+    stutter_gate: Smoother,
+    /// Slower running mean square of the live input, held through silence,
+    /// for matching the frozen level to it.
+    loudness: f64,
+    /// Frames since the input was last louder than silence.
+    since_content: usize,
+    auto_freeze: AutoFreeze,
+    /// Keys down in mono and chord modes with their velocities, in the
+    /// order pressed.
+    held_notes: Vec<(u8, u8)>,
+    /// A chord started during the frame's events, to capture once they're
+    /// all in, see `start_chord`.
+    chord_pending: bool,
+    /// Note-ons waiting out the attack hold before they freeze.
+    pending_notes: Vec<PendingNote>,
+    /// Settings of the frame being processed, see `begin_frame`.
+    playback: Playback,
+    /// Randomness for the granular mode.
+    rng: XorShift,
+    /// Seed `rng` last started from, to start over when it changes.
+    seed: u32,
+    /// Incremented on every note-on to find the oldest voice when stealing.
+    voice_clock: u64,
+    /// How far the output has faded over to the bypassed input, 1 skipping
+    /// all processing.
+    bypass_level: f64,
+    /// DC blocker state of the frozen signal per channel.
+    dc_blockers: Vec<DcBlocker>,
+    /// Lo-fi state of the frozen signal per channel.
+    degraders: Vec<Degrader>,
+    /// Filter state of the frozen signal per channel.
+    filters: Vec<Filter>,
+    scratch: FrameScratch,
+}
+
+/// Working space for a frame of every channel, allocated up front so that
+/// processing never allocates however wide the bus is.
+#[derive(Default)]
+struct FrameScratch {
+    /// Frame `process_frame` captures from as it plays through.
+    capture: Vec<f64>,
+    source: Vec<f64>,
+    dry: Vec<f64>,
+    wet: Vec<f64>,
+    output: Vec<f64>,
+}
+
+impl FrameScratch {
+    fn new(channels: usize) -> Self {
+        FrameScratch {
+            capture: Vec::with_capacity(channels),
+            source: vec![0.0; channels],
+            dry: vec![0.0; channels],
+            wet: vec![0.0; channels],
+            output: vec![0.0; channels],
+        }
+    }
+}
+
+/// Settings an engine starts out with, which the parameters don't cover.
+#[derive(Clone, Copy, Debug)]
+pub struct FreezeConfig {
+    /// Rate assumed until `FreezeEngine::set_sample_rate` says otherwise.
+    pub sample_rate: f64,
+    /// Highest rate the buffers hold full windows for without growing.
+    pub preallocated_rate: f64,
+    /// Length of the fixed fades keeping bypass, note-ons and re-triggers
+    /// from clicking, at least one.
+    pub xfade_frames: usize,
+}
+
+impl Default for FreezeConfig {
+    fn default() -> Self {
+        FreezeConfig {
+            sample_rate: 48_000.0,
+            preallocated_rate: PREALLOCATED_RATE,
+            xfade_frames: XFADE_FRAMES,
+        }
+    }
+}
+
+impl FreezeEngine {
+    /// Engine for frames of `channels`, as wide as the bus it processes.
+    pub fn new(params: Arc<ZamerzikaParameters>, channels: usize, config: FreezeConfig) -> Self {
+        let (speed, controls) = (Smoother(params.speed()), Controls::new(&params));
+        let octave = Smoother(f64::from(params.octave()));
+        let max_window = max_window_size(config.preallocated_rate.max(config.sample_rate));
+        let xfade_frames = config.xfade_frames.max(1);
+        let seed = params.seed();
+        FreezeEngine {
+            params,
+            sample_rate: config.sample_rate,
+            channels,
+            max_window,
+            xfade_frames,
+            input: ring_buffers(channels, max_window),
+            refreeze: ring_buffers(channels, max_window),
+            voices: (0..MAX_VOICES)
+                .map(|_| Voice::new(channels, max_window, xfade_frames))
+                .collect(),
+            bend: 0.0,
+            channel_pressure: 0.0,
+            speed,
+            octave,
+            mod_wheel: 0.0,
+            modulation: Smoother(0.0),
+            controls,
+            tempo: None,
+            playing: false,
+            sustain_held: false,
+            latched: false,
+            holding: false,
+            soloing: false,
+            input_level: 0.0,
+            envelope: 0.0,
+            input_power: 0.0,
+            loudness: 0.0,
+            stutter_phase: 0.0,
+            stutter_gate: Smoother(1.0),
+            since_content: 0,
+            auto_freeze: AutoFreeze::Idle,
+            held_notes: Vec::with_capacity(MIDI_NOTES),
+            chord_pending: false,
+            pending_notes: Vec::with_capacity(MIDI_NOTES),
+            playback: Playback::default(),
+            rng: XorShift::new(seed),
+            seed,
+            voice_clock: 0,
+            bypass_level: 0.0,
+            dc_blockers: vec![DcBlocker::default(); channels],
+            degraders: vec![Degrader::default(); channels],
+            filters: vec![Filter::default(); channels],
+            scratch: FrameScratch::new(channels),
+        }
+    }
+
+    /// Whether any voice is playing a freeze or fading out of one.
+    pub fn is_frozen(&self) -> bool {
+        self.voices.iter().any(Voice::is_sounding)
+    }
+
+    /// Number of channels in a frame.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Hosts only change the rate while not processing, so this is where
+    /// buffers grow for rates needing longer windows, dropping what they
+    /// held.
+    pub fn set_sample_rate(&mut self, rate: f64) {
+        self.sample_rate = rate;
+        let max_window = max_window_size(rate);
+        if max_window > self.max_window {
+            self.max_window = max_window;
+            self.input = ring_buffers(self.channels, max_window);
+            self.refreeze = ring_buffers(self.channels, max_window);
+            for voice in self.voices.iter_mut() {
+                *voice = Voice::new(self.channels, max_window, self.xfade_frames);
+            }
+        }
+    }
+
+    /// Back to silence with no notes held or frozen, as when just created,
+    /// keeping the buffers already allocated.
+    pub fn reset(&mut self) {
+        for input in self.input.iter_mut().chain(self.refreeze.iter_mut()) {
+            input.resize(input.len(), 0.0);
+        }
+        for voice in self.voices.iter_mut() {
+            voice.reset();
+        }
+        self.bend = 0.0;
+        self.channel_pressure = 0.0;
+        self.speed = Smoother(self.params.speed());
+        self.octave = Smoother(f64::from(self.params.octave()));
+        self.mod_wheel = 0.0;
+        self.modulation = Smoother(0.0);
+        self.controls = Controls::new(&self.params);
+        self.sustain_held = false;
+        self.latched = false;
+        self.holding = false;
+        self.soloing = false;
+        self.input_level = 0.0;
+        self.envelope = 0.0;
+        self.seed = self.params.seed();
+        self.rng = XorShift::new(self.seed);
+        self.input_power = 0.0;
+        self.loudness = 0.0;
+        self.stutter_phase = 0.0;
+        self.stutter_gate = Smoother(1.0);
+        self.since_content = 0;
+        self.auto_freeze = AutoFreeze::Idle;
+        self.held_notes.clear();
+        self.chord_pending = false;
+        self.pending_notes.clear();
+        self.dc_blockers.fill(DcBlocker::default());
+        self.degraders.fill(Degrader::default());
+        self.filters.fill(Filter::default());
+    }
+
+    /// Tempo for synced windows, `None` falling back to pitch periods.
+    pub fn set_tempo(&mut self, tempo: Option<f64>) {
+        self.tempo = tempo;
+    }
+
+    /// Follow the host transport, releasing every freeze when it stops if
+    /// asked to. Starting again leaves them released.
+    pub fn set_playing(&mut self, playing: bool) {
+        if self.playing && !playing && self.params.switch(Parameter::ReleaseOnStop) {
+            self.held_notes.clear();
+            self.release_all(self.release_times());
+        }
+        if !self.playing && playing {
+            // Start a stutter cycle right away, on the beat the transport
+            // starts from.
+            self.stutter_phase = 1.0;
+        }
+        self.playing = playing;
+    }
+
+    /// Update the settings shared by every channel of the upcoming frame
+    /// and step the glides and the grain scheduler.
+    fn begin_frame(&mut self) {
+        // Nobody is holding the keys of a latched freeze, so turning latch
+        // off lets go of it.
+        if self.latched && !self.params.switch(Parameter::Latch) {
+            self.latched = false;
+            self.release_all(self.release_times());
+        }
+        // Soloing keeps the loops going to listen to, the way hold does.
+        let holding = self.params.switch(Parameter::Hold) || self.params.switch(Parameter::SoloWet);
+        if self.holding && !holding {
+            self.release_all(self.release_times());
+        }
+        self.holding = holding;
+        let soloing = self.params.switch(Parameter::SoloWet);
+        if soloing && !self.soloing && self.voices.iter().all(|voice| voice.note.is_none()) {
+            // Nothing held to listen to, so the loop frozen last comes back.
+            let last = self
+                .voices
+                .iter_mut()
+                .filter(|voice| voice.window_size > 0)
+                .max_by_key(|voice| voice.started);
+            if let Some(voice) = last {
+                voice.revive();
+            }
+        }
+        self.soloing = soloing;
+        if self.params.seed() != self.seed {
+            self.seed = self.params.seed();
+            self.rng = XorShift::new(self.seed);
+        }
+        let coefficient = self.smoothing_coefficient(SPEED_SMOOTH_MS);
+        let mut speed = self.speed.advance(self.params.speed(), coefficient);
+        let octave = self
+            .octave
+            .advance(f64::from(self.params.octave()), coefficient);
+        let modulation = self.modulation.advance(self.mod_wheel, coefficient);
+        let coefficient = self.smoothing_coefficient(PARAM_SMOOTH_MS);
+        let controls = &mut self.controls;
+        let mut mix = controls
+            .mix
+            .advance(f64::from(self.params.get(Parameter::Mix)), coefficient);
+        // Glide the cutoff in octaves, so sweeps sound even throughout.
+        let mut cutoff = controls
+            .cutoff
+            .advance(self.params.cutoff().log2(), coefficient)
+            .exp2();
+        match self.params.mod_target() {
+            // Down by up to two octaves, a tape slowing to a crawl.
+            ModTarget::Speed => speed *= (-2.0 * modulation).exp2(),
+            ModTarget::Mix => mix *= 1.0 - modulation,
+            ModTarget::Cutoff => cutoff *= (-MOD_CUTOFF_OCTAVES * modulation).exp2(),
+        }
+        // Follows the last frame's input, a frame late being inaudible.
+        cutoff *= (self.params.envelope_octaves() * self.envelope.min(1.0)).exp2();
+        let resonance = controls
+            .resonance
+            .advance(self.params.resonance(), coefficient);
+        let filter = self
+            .params
+            .filter_kind()
+            .map(|kind| FilterSettings::new(kind, cutoff, resonance, self.sample_rate));
+        let pan = controls.pan.advance(self.params.pan(), coefficient);
+        let width = controls.width.advance(self.params.width(), coefficient);
+        self.playback = Playback {
+            // A centred wheel is exactly 0 whatever the range, so there's no
+            // detuning at rest.
+            stride: (self.bend * self.params.bend_range() / 12.0 + octave).exp2() * speed,
+            mix,
+            solo: self.params.switch(Parameter::SoloWet),
+            direction: self.params.direction(),
+            curve: self.params.curve(),
+            feedback: controls
+                .feedback
+                .advance(self.params.feedback(), coefficient),
+            stutter_gate: self.playback.stutter_gate,
+            shimmer_level: controls
+                .shimmer_level
+                .advance(self.params.shimmer_level(), coefficient),
+            shimmer_feedback: controls
+                .shimmer_feedback
+                .advance(self.params.shimmer_feedback(), coefficient),
+            oversampling: self.params.oversampling(),
+            interpolation: self.params.interpolation(),
+            mode: self.params.freeze_mode(),
+            bits: self.params.bits(),
+            hold: self.params.downsample(),
+            stereo: Stereo::new(pan, width),
+            pressure_depth: f64::from(self.params.get(Parameter::Aftertouch)),
+            filter,
+            input_gain: controls
+                .input_gain
+                .advance(self.params.input_gain(), coefficient),
+            gain: controls.gain.advance(self.params.gain(), coefficient),
+            ceiling: controls.ceiling.advance(self.params.ceiling(), coefficient),
+        };
+        let strength = self.params.level_match();
+        let kept = 1.0 - self.playback.shimmer_feedback;
+        for voice in self.voices.iter_mut() {
+            if voice.clearing && !voice.is_sounding() {
+                voice.reset();
+            }
+            voice.advance_glide();
+            voice.match_gain = match_gain(voice.power, self.loudness, strength);
+            // Every sample is blended once per pass, which comes round
+            // faster for shorter loops.
+            voice.shimmer_blend = 1.0 - kept.powf(voice.window_size as f64 / self.sample_rate);
+        }
+        self.advance_stutter();
+        self.start_chord();
+        self.advance_pending_notes();
+        if let Some(cap) = self.hold_cap_frames() {
+            let release = self.release_times();
+            for voice in self.voices.iter_mut().filter(|voice| voice.note.is_some()) {
+                voice.held_frames += 1;
+                if voice.held_frames >= cap {
+                    voice.release(release);
+                }
+            }
+        }
+        if self.playback.mode == FreezeMode::Granular {
+            let grains = GrainSettings {
+                length: self.ms_to_frames(self.params.grain_ms()).max(1),
+                interval: self.sample_rate / self.params.grain_density(),
+                spread: self.params.grain_spread(),
+            };
+            for voice in self.voices.iter_mut() {
+                if voice.is_sounding() {
+                    voice.advance_grains(&self.playback, &grains, &mut self.rng);
+                }
+            }
+        }
+    }
+
+    /// Process a frame of all channels in place. Samples past `channels`
+    /// are left as they are.
+    pub fn process_frame(&mut self, frame: &mut [f64]) {
+        let channels = frame.len().min(self.channels);
+        let frame = &mut frame[..channels];
+        let mut capture = std::mem::take(&mut self.scratch.capture);
+        capture.clear();
+        capture.extend_from_slice(frame);
+        self.process_sidechain_frame(frame, &capture);
+        self.scratch.capture = capture;
+    }
+
+    /// Like `process_frame`, but capturing `sidechain` while `frame` plays
+    /// through.
+    pub fn process_sidechain_frame(&mut self, frame: &mut [f64], sidechain: &[f64]) {
+        let channels = frame.len().min(sidechain.len()).min(self.channels);
+        let (frame, sidechain) = (&mut frame[..channels], &sidechain[..channels]);
+        let mut scratch = std::mem::take(&mut self.scratch);
+        self.process_scratch_frame(frame, sidechain, &mut scratch);
+        self.scratch = scratch;
+    }
+
+    /// `process_sidechain_frame` for frames no wider than the engine, with
+    /// `scratch` to work in.
+    fn process_scratch_frame(
+        &mut self,
+        frame: &mut [f64],
+        sidechain: &[f64],
+        scratch: &mut FrameScratch,
+    ) {
+        let channels = frame.len();
+        self.begin_frame();
+        // Fade in and out of bypass rather than jumping between the input
+        // and whatever is playing.
+        let step = 1.0 / self.xfade_frames as f64;
+        self.bypass_level = if self.params.switch(Parameter::Bypass) {
+            (self.bypass_level + step).min(1.0)
+        } else {
+            (self.bypass_level - step).max(0.0)
+        };
+        // Trim what's captured and, below, what plays live alike, but not
+        // what bypass passes through.
+        let trim = self.playback.input_gain;
+        let source = &mut scratch.source[..channels];
+        for (source, &sample) in source.iter_mut().zip(sidechain) {
+            *source = trim * sample;
+        }
+        if self.params.switch(Parameter::MonoInput) {
+            // Every channel captures the same, so nothing cancels out
+            // between them once frozen.
+            let sum = source.iter().sum::<f64>() / channels.max(1) as f64;
+            source.fill(sum);
+        }
+        let sidechain = &*source;
+        self.track_content(sidechain);
+        self.track_loudness(frame, trim);
+        self.track_envelope(frame, trim);
+        if self.bypass_level == 1.0 {
+            // Keep recording, so the first capture after bypass finds the
+            // latest input.
+            for (input, &sample) in self.input.iter_mut().zip(sidechain) {
+                input.write(sample);
+            }
+            return;
+        }
+        self.advance_trigger(sidechain);
+        let dry = &mut scratch.dry[..channels];
+        dry.copy_from_slice(frame);
+        let wet = &mut scratch.wet[..channels];
+        for (channel, (sample, &source)) in frame.iter_mut().zip(sidechain).enumerate() {
+            let (frozen, live) = self.process_sample(channel, trim * *sample, source);
+            wet[channel] = frozen;
+            *sample = live;
+        }
+        if let [left, right] = wet {
+            self.playback.stereo.apply(left, right);
+        }
+        let output = &mut scratch.output[..channels];
+        for (channel, (sample, &mut wet)) in frame.iter().zip(wet).enumerate() {
+            // Only the loops build up an offset, so the live signal passes
+            // through untouched. Keep the filter running while it's
+            // bypassed, so switching it back on doesn't jump.
+            let wet = wet * self.playback.gain;
+            let blocked = self.dc_blockers[channel].process(wet);
+            let wet = if self.params.switch(Parameter::DcBlock) {
+                blocked
+            } else {
+                wet
+            };
+            output[channel] = *sample * self.playback.gain + wet;
+        }
+        if let Some(limiter) = self.params.limiter() {
+            limiter.apply(output, self.playback.ceiling);
+        }
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            *sample = output[channel] + self.bypass_level * (dry[channel] - output[channel]);
+        }
+    }
+
+    /// Frozen and live parts of the output of `channel`, before the stereo
+    /// stage works on the frozen ones, capturing from `source`.
+    fn process_sample(&mut self, channel: usize, sample: f64, source: f64) -> (f64, f64) {
+        self.input[channel].write(source);
+        let playback = self.playback;
+        let mix = playback.mix;
+        // Sum every sounding voice, weighting them by their envelopes.
+        let mut wet = 0.0;
+        let mut presence = 0.0;
+        for voice in self.voices.iter_mut() {
+            if let Some(level) = voice.advance_level(channel) {
+                let (fade, _) = playback.curve.gains(level);
+                let pressure = voice.pressure.max(self.channel_pressure);
+                let gain = fade
+                    * voice.velocity_gain
+                    * voice.match_gain
+                    * (1.0 + playback.pressure_depth * pressure);
+                wet += gain * voice.read(channel, source, &playback);
+                presence += level;
+            }
+        }
+        let curve = playback.curve;
+        if presence == 0.0 {
+            // Start the next freeze without the ringing of the last one.
+            self.filters[channel] = Filter::default();
+            self.refreeze[channel].write(0.0);
+            return (0.0, if playback.solo { 0.0 } else { sample });
+        }
+        // Overlapping loops are mostly uncorrelated, so keep their summed
+        // power in check rather than their amplitude.
+        if presence > 1.0 {
+            wet /= presence.sqrt();
+        }
+        let wet = wet * playback.stutter_gate;
+        let mut wet = self.degraders[channel].process(wet, &playback);
+        if let Some(settings) = &playback.filter {
+            wet = self.filters[channel].process(wet, settings);
+        }
+        self.refreeze[channel].write(wet);
+        if playback.solo {
+            return (wet, 0.0);
+        }
+        // Fade out from the mixed level rather than from the full loop.
+        let (_, dry) = curve.gains(presence.min(1.0));
+        (mix * wet, mix * dry * sample + (1.0 - mix) * sample)
+    }
+
+    /// Apply a MIDI channel message, ignoring anything else, see
+    /// `channel_message`.
+    pub fn process_midi(&mut self, data: [u8; 3]) {
+        let Some(data) = channel_message(data) else {
+            return;
+        };
+        let (status, channel) = (data[0] & 0xF0, data[0] & 0x0F);
+        if self
+            .params
+            .channel_filter()
+            .is_some_and(|filter| filter != channel)
+        {
+            return;
+        }
+        match status {
+            0x80 => self.note_off(data[1], data[2]),
+            // Running status streams send note-offs as zero velocity
+            // note-ons, which can't carry a release velocity: assume the
+            // default one.
+            0x90 if data[2] == 0 => self.note_off(data[1], DEFAULT_RELEASE_VELOCITY),
+            0x90 => self.note_on(data[1], data[2]),
+            0xA0 => {
+                for voice in self.voices.iter_mut() {
+                    if voice.note == Some(data[1]) {
+                        voice.pressure = f64::from(data[2]) / 127.0;
+                    }
+                }
+            }
+            0xB0 => self.control_change(data[1], data[2]),
+            0xD0 => self.channel_pressure = f64::from(data[1]) / 127.0,
+            0xE0 => {
+                // 14-bit value, least significant 7 bits first, centred at 0x2000.
+                let bend = i32::from(data[1]) | (i32::from(data[2]) << 7);
+                self.bend = f64::from(bend - 0x2000) / f64::from(0x2000);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn note_on(&mut self, pitch: u8, velocity: u8) {
+        // Playing takes over from the trigger mode.
+        if let AutoFreeze::Frozen(frozen) = self.auto_freeze {
+            self.release_key(frozen);
+        }
+        self.auto_freeze = AutoFreeze::Idle;
+        if self.params.switch(Parameter::Latch) {
+            // Every other note-on ends the latched freeze instead of
+            // starting one.
+            self.latched = !self.latched;
+            if !self.latched {
+                self.release_all(self.release_times());
+                return;
+            }
+        }
+        if self.params.switch(Parameter::Chord) {
+            self.held_notes.retain(|&(held, _)| held != pitch);
+            self.held_notes.push((pitch, velocity));
+            // The first key starts the chord, and the rest only join in.
+            if self.held_notes.len() == 1 {
+                self.chord_pending = true;
+            }
+            return;
+        }
+        if self.params.switch(Parameter::Mono) {
+            self.held_notes.retain(|&(held, _)| held != pitch);
+            self.held_notes.push((pitch, velocity));
+            // A new note only sounds if it takes priority over the held ones.
+            if self.mono_note() == Some((pitch, velocity)) {
+                self.change_mono_note(pitch, velocity);
+            }
+            return;
+        }
+        self.trigger(pitch, velocity, None);
+    }
+
+    /// Freeze `pitch`, or retune a held freeze to it when playing legato.
+    /// A `length` in frames stands in for the window the pitch would have.
+    fn trigger(&mut self, pitch: u8, velocity: u8, length: Option<f64>) {
+        let freq = self.params.note_freq(pitch);
+        let glide_frames = self.ms_to_frames(self.params.glide_ms());
+        if self.params.switch(Parameter::Legato) {
+            // Overlapping notes retune the phrase's loop rather than
+            // capturing a new one.
+            let held = self
+                .voices
+                .iter_mut()
+                .filter(|voice| voice.note.is_some() && !voice.layer)
+                .max_by_key(|voice| voice.started);
+            if let Some(voice) = held {
+                voice.note = Some(pitch);
+                voice.pitch = pitch;
+                voice.held_frames = 0;
+                voice.sustained = false;
+                voice.glide_to(freq / voice.freq, glide_frames);
+                return;
+            }
+        }
+        let hold = self.ms_to_frames(self.params.attack_hold_ms());
+        if hold > 0 {
+            // Let the attack play through live, capturing what follows it.
+            self.pending_notes.retain(|note| note.pitch != pitch);
+            self.pending_notes.push(PendingNote {
+                pitch,
+                velocity,
+                length,
+                countdown: hold,
+            });
+            return;
+        }
+        self.freeze(pitch, velocity, length);
+    }
+
+    /// Capture a new loop for `pitch`, or re-capture its voice's, see
+    /// `trigger` for `length`.
+    fn freeze(&mut self, pitch: u8, velocity: u8, length: Option<f64>) {
+        let freq = self.params.note_freq(pitch);
+        // Within the same bounds as the pitch's own window.
+        let window_length = match length {
+            Some(length) => length.clamp(1.0, self.max_window as f64),
+            None => self.window_length(freq),
+        };
+        let window_size = (window_length.round() as usize).clamp(1, self.max_window);
+        // Only once there's something frozen to capture, the first note
+        // taking the input either way.
+        let refreeze =
+            self.params.capture_source() == CaptureSource::FrozenOutput && self.is_frozen();
+        let capture = CaptureSettings {
+            window_size,
+            window_length,
+            depth: self.smooth_frames(),
+            direction: self.params.direction(),
+            linked: self.params.switch(Parameter::StereoLink),
+            align: self.params.switch(Parameter::ZeroCross),
+            // The silence snapping skips is the input's.
+            offset: if refreeze {
+                0
+            } else {
+                self.content_offset(window_size)
+            },
+            seam: self.params.seam_mode(),
+            // A whole window back would just line up again.
+            haas: self
+                .ms_to_frames(self.params.haas_ms())
+                .min(window_size.saturating_sub(1)),
+            // Past the end of the window would wrap round to the start.
+            spread: self
+                .ms_to_frames(self.params.channel_spread_ms())
+                .min(window_size.saturating_sub(1)),
+        };
+        let attack_frames = self.ms_to_frames(self.params.attack_ms());
+        self.voice_clock += 1;
+        let layer = self.params.switch(Parameter::Layer);
+        let index = self.allocate_voice(pitch, layer);
+        let voice = &mut self.voices[index];
+        let retrigger = voice.note == Some(pitch);
+        voice.note = Some(pitch);
+        voice.pitch = pitch;
+        voice.started = self.voice_clock;
+        voice.held_frames = 0;
+        voice.sustained = false;
+        voice.layer = layer;
+        voice.clearing = false;
+        voice.velocity_gain = self.params.velocity_gain(velocity);
+        voice.pressure = 0.0;
+        voice.freq = freq;
+        voice.glide_to(1.0, 0);
+        voice.attack_step = envelope_step(attack_frames);
+        let source = if refreeze {
+            &mut self.refreeze
+        } else {
+            &mut self.input
+        };
+        voice.capture(source, &capture);
+        if retrigger {
+            // Carry on at the level already reached, fading from the old
+            // loop to the new one instead of cutting over.
+            voice.retrigger_countdown.fill(voice.xfade_frames);
+        } else {
+            voice.envelope.fill(0.0);
+            voice.xfade_in_countdown.fill(voice.xfade_frames);
+        }
+    }
+
+    pub fn note_off(&mut self, pitch: u8, velocity: u8) {
+        if self.latched {
+            return;
+        }
+        if self.holding {
+            // Only all notes off or switching hold off end the freezes, but
+            // the key is up as far as mono mode goes.
+            self.held_notes.retain(|&(held, _)| held != pitch);
+            return;
+        }
+        // A chord lets go once its last key is up, whichever that is.
+        let chord = self.params.switch(Parameter::Chord);
+        if chord {
+            self.held_notes.retain(|&(held, _)| held != pitch);
+            if !self.held_notes.is_empty() {
+                return;
+            }
+            self.chord_pending = false;
+        } else if self.params.switch(Parameter::Mono) {
+            let sounding = self.mono_note();
+            self.held_notes.retain(|&(held, _)| held != pitch);
+            if sounding.map(|(sounding, _)| sounding) != Some(pitch) {
+                return;
+            }
+            // Fall back to the held note which now takes priority.
+            if let Some((next, velocity)) = self.mono_note() {
+                self.change_mono_note(next, velocity);
+                return;
+            }
+        }
+        let crossfade = self.ms_to_frames(self.params.release_xfade_ms(velocity));
+        let release = ReleaseTimes {
+            crossfade: crossfade.min(self.max_window),
+            ..self.release_times()
+        };
+        // A key up before its attack hold is over never freezes.
+        self.pending_notes
+            .retain(|note| note.pitch != pitch && !chord);
+        for voice in self.voices.iter_mut() {
+            let keyed = voice.note == Some(pitch) || (chord && voice.note.is_some());
+            if keyed && !voice.layer {
+                if self.sustain_held {
+                    voice.sustained = true;
+                } else {
+                    voice.release(release);
+                }
+            }
+        }
+    }
+
+    /// Held key which sounds in mono mode according to its priority.
+    fn mono_note(&self) -> Option<(u8, u8)> {
+        let held = self.held_notes.iter().copied();
+        match self.params.note_priority() {
+            NotePriority::Last => self.held_notes.last().copied(),
+            NotePriority::Highest => held.max_by_key(|&(pitch, _)| pitch),
+            NotePriority::Lowest => held.min_by_key(|&(pitch, _)| pitch),
+        }
+    }
+
+    /// Move the mono freeze over to `pitch`. Legato retunes the sounding
+    /// note, otherwise it makes way for a new capture.
+    fn change_mono_note(&mut self, pitch: u8, velocity: u8) {
+        if !self.params.switch(Parameter::Legato) {
+            let release = self.release_times();
+            for voice in self.voices.iter_mut() {
+                if voice.note.is_some_and(|note| note != pitch) && !voice.layer {
+                    voice.release(release);
+                }
+            }
+        }
+        self.trigger(pitch, velocity, None);
+    }
+
+    pub fn control_change(&mut self, controller: u8, value: u8) {
+        if self.params.reset_controller() == Some(controller) {
+            if value >= 64 {
+                self.clear();
+            }
+            return;
+        }
+        match controller {
+            CC_MODWHEEL => self.mod_wheel = f64::from(value) / 127.0,
+            CC_SUSTAIN => {
+                self.sustain_held = value >= 64;
+                if !self.sustain_held {
+                    let release = self.release_times();
+                    for voice in self.voices.iter_mut() {
+                        if voice.sustained {
+                            voice.release(release);
+                        }
+                    }
+                }
+            }
+            // Panic buttons must not leave anything frozen, pedal or not.
+            CC_ALL_SOUND_OFF => {
+                self.held_notes.clear();
+                self.release_all(ReleaseTimes::IMMEDIATE);
+            }
+            CC_ALL_NOTES_OFF => {
+                self.held_notes.clear();
+                self.release_all(self.release_times());
+            }
+            CC_CLEAR_LAYERS if value >= 64 => {
+                let release = self.release_times();
+                for voice in self.voices.iter_mut() {
+                    if voice.layer && voice.note.is_some() {
+                        voice.release(release);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Length of the window captured for a note at `freq`: a beat division
+    /// when synced to a host which reports its tempo, otherwise pitch
+    /// periods.
+    fn window_size(&self, freq: f64) -> usize {
+        (self.window_length(freq).round() as usize).clamp(1, self.max_window)
+    }
+
+    /// Exact length in frames the window of `window_size` stands for,
+    /// usually not a whole number.
+    fn window_length(&self, freq: f64) -> f64 {
+        let max_window = self.max_window as f64;
+        if let Some(tempo) = self.tempo.filter(|_| self.params.switch(Parameter::Sync)) {
+            let beats = 4.0 / self.params.division() as f64;
+            let frames = beats * 60.0 / tempo * self.sample_rate;
+            return frames.clamp(1.0, max_window);
+        }
+        let period = period_length(self.sample_rate, freq, self.max_window);
+        // As many of the periods asked for as fit, keeping the loop a whole
+        // number of them.
+        let periods = self.params.periods().min((max_window / period) as usize);
+        period * periods.max(1) as f64
+    }
+
+    /// Share of the way a control smoothed with a time constant of `ms`
+    /// moves towards its target every frame.
+    fn smoothing_coefficient(&self, ms: f64) -> f64 {
+        1.0 - (-1000.0 / (ms * self.sample_rate)).exp()
+    }
+
+    /// Step the stutter through its cycle: the loops play through the first
+    /// half and are gated by the depth in the second, starting over from
+    /// the top of the window on every cycle.
+    fn advance_stutter(&mut self) {
+        let (Some(rate), depth) = (self.stutter_rate(), self.params.stutter_depth()) else {
+            self.stutter_phase = 0.0;
+            self.playback.stutter_gate = self.stutter_gate.advance(1.0, 1.0);
+            return;
+        };
+        self.stutter_phase += rate / self.sample_rate;
+        if self.stutter_phase >= 1.0 {
+            self.stutter_phase -= self.stutter_phase.floor();
+            let direction = self.playback.direction;
+            let ramp = (STUTTER_RAMP_MS / 1000.0 * self.sample_rate).round() as usize;
+            for voice in self.voices.iter_mut().filter(|voice| voice.is_sounding()) {
+                voice.restart(direction, ramp.max(1));
+            }
+        }
+        let target = if self.stutter_phase < 0.5 {
+            1.0
+        } else {
+            1.0 - depth
+        };
+        let coefficient = self.smoothing_coefficient(STUTTER_SMOOTH_MS);
+        self.playback.stutter_gate = self.stutter_gate.advance(target, coefficient);
+    }
+
+    /// Capture the chord started since the last frame for its lowest key.
+    /// Keys landing together reach `process_midi` in whatever order the
+    /// host sends them, so that's only known once the frame's events are
+    /// all in.
+    fn start_chord(&mut self) {
+        if !std::mem::take(&mut self.chord_pending) {
+            return;
+        }
+        if let Some(&(pitch, velocity)) = self.held_notes.iter().min() {
+            self.trigger(pitch, velocity, None);
+        }
+    }
+
+    /// Count down the attack holds, freezing the notes whose hold is over.
+    fn advance_pending_notes(&mut self) {
+        let mut index = 0;
+        while let Some(note) = self.pending_notes.get_mut(index) {
+            if note.countdown > 0 {
+                note.countdown -= 1;
+                index += 1;
+            } else {
+                let note = self.pending_notes.remove(index);
+                self.freeze(note.pitch, note.velocity, note.length);
+            }
+        }
+    }
+
+    /// Stutter cycles per second, a beat division when synced to a host
+    /// which reports its tempo, or `None` when off.
+    fn stutter_rate(&self) -> Option<f64> {
+        // Synced to a known tempo, the division alone sets the rate.
+        match self
+            .tempo
+            .filter(|_| self.params.switch(Parameter::StutterSync))
+        {
+            Some(tempo) => Some(tempo / 60.0 / self.params.stutter_beats()),
+            None => self.params.stutter_rate(),
+        }
+    }
+
+    /// Window length in frames the trigger mode freezes, if set rather than
+    /// following the trigger note.
+    fn trigger_window_length(&self) -> Option<f64> {
+        let ms = self.params.trigger_window_ms()?;
+        Some(f64::from(ms) / 1000.0 * self.sample_rate)
+    }
+
+    /// Follow the input level, freezing automatically when it's higher than
+    /// the threshold and releasing once it falls below the release one.
+    fn advance_trigger(&mut self, frame: &[f64]) {
+        let peak = frame
+            .iter()
+            .fold(0.0, |peak: f64, sample| peak.max(sample.abs()));
+        let ms = if peak > self.input_level {
+            FOLLOWER_ATTACK_MS
+        } else {
+            FOLLOWER_RELEASE_MS
+        };
+        self.input_level += self.smoothing_coefficient(ms) * (peak - self.input_level);
+        if !self.params.switch(Parameter::Trigger) {
+            if let AutoFreeze::Frozen(pitch) = self.auto_freeze {
+                self.release_key(pitch);
+            }
+            self.auto_freeze = AutoFreeze::Idle;
+            return;
+        }
+        let threshold = self.params.threshold();
+        let released = self.input_level < self.params.release_threshold().min(threshold);
+        self.auto_freeze = match self.auto_freeze {
+            AutoFreeze::Idle => {
+                // Keys and latched freezes have the say while there are any.
+                let held = self.latched || self.voices.iter().any(|voice| voice.note.is_some());
+                if self.input_level >= threshold && !held {
+                    // Wait for a window's worth of the loud input, rather
+                    // than capturing what led up to it.
+                    let window_size = match self.trigger_window_length() {
+                        Some(length) => (length.round() as usize).clamp(1, self.max_window),
+                        None => self.window_size(self.params.note_freq(self.params.trigger_note())),
+                    };
+                    AutoFreeze::Pending(window_size)
+                } else {
+                    AutoFreeze::Idle
+                }
+            }
+            AutoFreeze::Pending(_) if released => AutoFreeze::Idle,
+            AutoFreeze::Pending(0) => {
+                let pitch = self.params.trigger_note();
+                self.trigger(pitch, 127, self.trigger_window_length());
+                AutoFreeze::Frozen(pitch)
+            }
+            AutoFreeze::Pending(countdown) => AutoFreeze::Pending(countdown - 1),
+            AutoFreeze::Frozen(pitch) if released => {
+                self.release_key(pitch);
+                AutoFreeze::Idle
+            }
+            frozen => frozen,
+        };
+    }
+
+    /// Measure the loudness of the frame being recorded, to know where the
+    /// input last had something in it.
+    fn track_content(&mut self, frame: &[f64]) {
+        let power =
+            frame.iter().map(|sample| sample * sample).sum::<f64>() / frame.len().max(1) as f64;
+        self.input_power += self.smoothing_coefficient(CONTENT_RMS_MS) * (power - self.input_power);
+        // The average keeps lone clicks in silence from counting, while the
+        // frame itself marks where the content ends rather than where the
+        // average decays.
+        if self.input_power >= SILENCE_POWER && power >= SILENCE_POWER {
+            self.since_content = 0;
+        } else {
+            self.since_content = self.since_content.saturating_add(1);
+        }
+    }
+
+    /// Follow the loudness of the live input as trimmed by `trim`, keeping
+    /// the last level heard while it's silent.
+    fn track_loudness(&mut self, frame: &[f64], trim: f64) {
+        let power = frame
+            .iter()
+            .map(|sample| (trim * sample).powi(2))
+            .sum::<f64>()
+            / frame.len().max(1) as f64;
+        if power >= SILENCE_POWER {
+            self.loudness += self.smoothing_coefficient(LOUDNESS_MS) * (power - self.loudness);
+        }
+    }
+
+    /// Follow the peak level of the live input as trimmed by `trim`, for
+    /// the envelope to open the filter with.
+    fn track_envelope(&mut self, frame: &[f64], trim: f64) {
+        let peak = frame
+            .iter()
+            .fold(0.0, |peak: f64, sample| peak.max((trim * sample).abs()));
+        let ms = if peak > self.envelope {
+            self.params.envelope_attack_ms()
+        } else {
+            self.params.envelope_release_ms()
+        };
+        self.envelope += self.smoothing_coefficient(f64::from(ms)) * (peak - self.envelope);
+    }
+
+    /// Frames back from the latest input a window of `window_size` ends
+    /// at: where the input last wasn't silent when snapping to content, as
+    /// long as that's still recorded.
+    fn content_offset(&self, window_size: usize) -> usize {
+        let recorded = self.input.first().map_or(0, RingBuffer::len);
+        if self.params.switch(Parameter::Snap) && self.since_content + window_size <= recorded {
+            self.since_content
+        } else {
+            0
+        }
+    }
+
+    /// Release the freeze of `pitch` as if its key went up, pedal aside.
+    fn release_key(&mut self, pitch: u8) {
+        self.pending_notes.retain(|note| note.pitch != pitch);
+        let release = self.release_times();
+        for voice in self.voices.iter_mut() {
+            if voice.note == Some(pitch) && !voice.layer {
+                voice.release(release);
+            }
+        }
+    }
+
+    /// Release every voice.
+    fn release_all(&mut self, release: ReleaseTimes) {
+        self.pending_notes.clear();
+        for voice in self.voices.iter_mut() {
+            // Voices already fading out carry on, unless silencing outright.
+            if voice.note.is_some() || release == ReleaseTimes::IMMEDIATE {
+                voice.release(release);
+            }
+        }
+    }
+
+    /// Fade out every voice over the cross-fade, whatever keeps it going,
+    /// and forget what they captured once silent.
+    fn clear(&mut self) {
+        self.held_notes.clear();
+        self.chord_pending = false;
+        self.pending_notes.clear();
+        self.latched = false;
+        self.auto_freeze = AutoFreeze::Idle;
+        let release = ReleaseTimes {
+            envelope: 0,
+            ..self.release_times()
+        };
+        for voice in self.voices.iter_mut().filter(|voice| voice.is_sounding()) {
+            voice.release(release);
+            voice.clearing = true;
+        }
+    }
+
+    /// Frames the output can go on sounding for after the last note-off,
+    /// for hosts to render before they stop processing.
+    pub fn tail_frames(&self) -> usize {
+        let release = self.release_times();
+        let mut tail = release
+            .crossfade
+            .max(release.envelope)
+            .max(self.xfade_frames);
+        if self.params.filter_kind().is_some() {
+            let mut cutoff = self.params.cutoff();
+            if self.params.mod_target() == ModTarget::Cutoff {
+                cutoff *= (-MOD_CUTOFF_OCTAVES).exp2();
+            }
+            // A resonance decays by a neper every 2Q/ω seconds, allow 60 dB
+            // of that.
+            let seconds = 1000f64.ln() * 2.0 * self.params.resonance() / (TAU * cutoff);
+            tail += (seconds * self.sample_rate).ceil() as usize;
+        }
+        if self.params.oversampling() > 1 {
+            // The decimation filter delays the loop by half its length.
+            tail += DECIMATOR_TAPS_PER_FACTOR / 2;
+        }
+        tail
+    }
+
+    /// Frames a freeze may play before it releases on its own, in beats
+    /// when synced to a host which reports its tempo, or `None` for no cap.
+    fn hold_cap_frames(&self) -> Option<usize> {
+        let beats = self.params.max_hold_beats();
+        let seconds = match self.tempo.filter(|_| self.params.switch(Parameter::Sync)) {
+            Some(tempo) if beats.is_some() => beats.map(|beats| beats as f64 * 60.0 / tempo),
+            _ => self.params.max_hold_seconds().map(f64::from),
+        }?;
+        Some(((seconds * self.sample_rate).round() as usize).max(1))
+    }
+
+    /// Note-off fade lengths at the current sample rate.
+    fn release_times(&self) -> ReleaseTimes {
+        ReleaseTimes {
+            crossfade: self.ms_to_frames(self.params.xfade_ms()),
+            envelope: self.ms_to_frames(self.params.release_ms()),
+        }
+    }
+
+    /// Depth of the loop seam smoothing in frames at the current sample rate.
+    fn smooth_frames(&self) -> usize {
+        self.ms_to_frames(self.params.smooth_ms())
+    }
+
+    fn ms_to_frames(&self, ms: f32) -> usize {
+        let seconds = f64::from(ms) / 1000.0;
+        (seconds * self.sample_rate).round() as usize
+    }
+
+    /// Pick a voice for `pitch`: the one already holding it, a silent one,
+    /// or failing that the one which started the longest time ago.
+    ///
+    /// A new `layer` never takes over the voice holding its pitch, so the
+    /// same note can be stacked.
+    fn allocate_voice(&self, pitch: u8, layer: bool) -> usize {
+        let voices = &self.voices;
+        let holding = if layer {
+            None
+        } else {
+            voices.iter().position(|voice| voice.note == Some(pitch))
+        };
+        holding
+            .or_else(|| voices.iter().position(|voice| !voice.is_sounding()))
+            .or_else(|| voices.iter().position(|voice| voice.note.is_none()))
+            .or_else(|| {
+                let oldest = voices
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, voice)| voice.started);
+                oldest.map(|(index, _)| index)
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// A single frozen note playing its own captured loop.
+struct Voice {
+    /// Key holding the voice, `None` once released.
+    note: Option<u8>,
+    /// Key the loop was captured or retuned for, kept once released.
+    pitch: u8,
+    /// Value of `FreezeEngine::voice_clock` when the voice was last triggered.
+    started: u64,
+    /// Frames played since the voice was last triggered, for the length cap.
+    held_frames: usize,
+    /// Key was released while the sustain pedal was down.
+    sustained: bool,
+    /// Captured as a layer, playing on after its key is up until the layers
+    /// are cleared.
+    layer: bool,
+    /// Fading out to be reset, see `FreezeEngine::clear`.
+    clearing: bool,
+    /// Level of the loop according to the note-on velocity.
+    velocity_gain: f64,
+    /// Mean square of the captured loop.
+    power: f64,
+    /// Level of the loop bringing it towards the live input's loudness.
+    match_gain: f64,
+    /// Latest poly aftertouch of the key, from 0 to 1.
+    pressure: f64,
+    /// Frequency of the note the loop was captured for.
+    freq: f64,
+    /// Playback rate relative to the captured pitch, following legato notes.
+    retune: f64,
+    /// Rate `retune` is gliding to, by a factor of `glide_step` per frame
+    /// for another `glide_countdown` frames.
+    retune_target: f64,
+    glide_step: f64,
+    glide_countdown: usize,
+    output: Vec<RingBuffer>,
+    /// Loop replaced by the latest capture, heard while a re-trigger fades
+    /// out of it.
+    previous: Vec<RingBuffer>,
+    /// Length of the re-trigger and note-on cross-fades, see
+    /// `FreezeConfig::xfade_frames`.
+    xfade_frames: usize,
+    /// Frames left of the re-trigger cross-fade per channel, out of
+    /// `xfade_frames`.
+    retrigger_countdown: Vec<usize>,
+    /// Frames left of the fade in after the stutter started the loop over
+    /// per channel, out of `restart_length`.
+    restart_countdown: Vec<usize>,
+    restart_length: usize,
+    window_size: usize,
+    /// Playback rate which stretches the loop of `window_size` to the exact
+    /// length it was rounded from, so its pitch is spot on.
+    correction: f64,
+    /// Frames into the loop the right channel starts at, see `capture`.
+    spread: usize,
+    /// Frames left of the note-on cross-fade from the live input per
+    /// channel, out of `xfade_frames`, so even a zero attack doesn't click.
+    xfade_in_countdown: Vec<usize>,
+    xfade_countdown: Vec<usize>,
+    /// Length of the release fade `xfade_countdown` started from.
+    xfade_length: usize,
+    /// Envelope level per channel, rising after note-on and falling after
+    /// note-off by the steps below.
+    envelope: Vec<f64>,
+    attack_step: f64,
+    release_step: f64,
+    /// Envelope level per channel when the voice was released, scaling the
+    /// note-off cross-fade so it starts where the envelope was.
+    release_level: Vec<f64>,
+    /// Read position of the octave up copy of the loop per channel.
+    shimmer_phase: Vec<f64>,
+    /// Oversampled loop reads per channel, see `read_loop`.
+    decimators: Vec<Decimator>,
+    /// Oversampling factor the decimators were last fed at per channel.
+    oversampling: Vec<usize>,
+    /// Share of every sample the octave up copy replaces per pass.
+    shimmer_blend: f64,
+    /// Grains being played in granular mode, free once fully aged.
+    grains: [Grain; MAX_GRAINS],
+    /// Position grains are scattered around, moving through the window.
+    grain_playhead: f64,
+    /// Frames until the next grain is due.
+    grain_countdown: f64,
+    /// Normalization of the summed grains according to their overlap.
+    grain_gain: f64,
+}
+
+impl Voice {
+    fn new(channels: usize, max_window: usize, xfade_frames: usize) -> Self {
+        Voice {
+            xfade_frames,
+            note: None,
+            pitch: 0,
+            started: 0,
+            held_frames: 0,
+            sustained: false,
+            layer: false,
+            clearing: false,
+            velocity_gain: 1.0,
+            power: 0.0,
+            match_gain: 1.0,
+            pressure: 0.0,
+            freq: 0.0,
+            retune: 1.0,
+            retune_target: 1.0,
+            glide_step: 1.0,
+            glide_countdown: 0,
+            output: ring_buffers(channels, max_window),
+            previous: ring_buffers(channels, max_window),
+            retrigger_countdown: vec![0; channels],
+            restart_countdown: vec![0; channels],
+            restart_length: 0,
+            window_size: 0,
+            correction: 1.0,
+            spread: 0,
+            xfade_in_countdown: vec![0; channels],
+            xfade_countdown: vec![0; channels],
+            xfade_length: 0,
+            envelope: vec![0.0; channels],
+            attack_step: f64::INFINITY,
+            release_step: f64::INFINITY,
+            release_level: vec![0.0; channels],
+            shimmer_phase: vec![0.0; channels],
+            decimators: vec![Decimator::default(); channels],
+            oversampling: vec![1; channels],
+            shimmer_blend: 0.0,
+            grains: Default::default(),
+            grain_playhead: 0.0,
+            grain_countdown: 0.0,
+            grain_gain: 1.0,
+        }
+    }
+
+    /// Silent and free, with nothing captured.
+    fn reset(&mut self) {
+        self.note = None;
+        self.sustained = false;
+        self.layer = false;
+        self.clearing = false;
+        self.pressure = 0.0;
+        self.glide_to(1.0, 0);
+        for ring in self.output.iter_mut().chain(self.previous.iter_mut()) {
+            ring.resize(0, 0.0);
+        }
+        self.window_size = 0;
+        self.correction = 1.0;
+        self.spread = 0;
+        self.power = 0.0;
+        self.retrigger_countdown.fill(0);
+        self.restart_countdown.fill(0);
+        self.xfade_in_countdown.fill(0);
+        self.xfade_countdown.fill(0);
+        self.envelope.fill(0.0);
+        self.release_level.fill(0.0);
+        self.grains = Default::default();
+        self.decimators.fill(Decimator::default());
+        self.oversampling.fill(1);
+    }
+
+    /// Jump back to the start of the loop, fading it in again over `ramp`
+    /// frames. A re-trigger fading out underneath carries on.
+    fn restart(&mut self, direction: PlayDirection, ramp: usize) {
+        for (channel, output) in self.output.iter_mut().enumerate() {
+            output.restart(direction);
+            if channel == 1 {
+                output.skip(self.spread);
+            }
+        }
+        self.restart_countdown.fill(ramp);
+        self.restart_length = ramp;
+    }
+
+    fn is_sounding(&self) -> bool {
+        self.note.is_some()
+            || self.xfade_countdown.iter().any(|&countdown| countdown > 0)
+            || self.envelope.iter().any(|&level| level > 0.0)
+    }
+
+    /// Step the envelopes of `channel` by a frame, returning the level the
+    /// voice plays at, or `None` once it's silent.
+    ///
+    /// A released voice fades with the slower of the cross-fade and the
+    /// release envelope, so either can lengthen the release.
+    fn advance_level(&mut self, channel: usize) -> Option<f64> {
+        let envelope = &mut self.envelope[channel];
+        if self.note.is_some() {
+            *envelope = (*envelope + self.attack_step).min(1.0);
+            // Whichever of the attack and the cross-fade is slower, the
+            // mirror of the note-off.
+            let countdown = &mut self.xfade_in_countdown[channel];
+            let crossfade = 1.0 - *countdown as f64 / self.xfade_frames as f64;
+            *countdown = countdown.saturating_sub(1);
+            return Some(crossfade.min(*envelope));
+        }
+        *envelope = (*envelope - self.release_step).max(0.0);
+        let countdown = &mut self.xfade_countdown[channel];
+        let crossfade = if *countdown > 0 {
+            let alpha = *countdown as f64 / self.xfade_length as f64;
+            *countdown -= 1;
+            alpha * self.release_level[channel]
+        } else {
+            0.0
+        };
+        let level = crossfade.max(*envelope);
+        (level > 0.0).then_some(level)
+    }
+
+    /// Slide the playback rate to `target` over `frames`, exponentially so
+    /// the pitch moves evenly, or jump there if `frames` is zero.
+    fn glide_to(&mut self, target: f64, frames: usize) {
+        self.retune_target = target;
+        self.glide_countdown = frames;
+        if frames == 0 {
+            self.retune = target;
+        } else {
+            self.glide_step = (target / self.retune).powf(1.0 / frames as f64);
+        }
+    }
+
+    fn advance_glide(&mut self) {
+        if self.glide_countdown > 0 {
+            self.glide_countdown -= 1;
+            self.retune = if self.glide_countdown == 0 {
+                // Land exactly, whatever the rounding along the way.
+                self.retune_target
+            } else {
+                self.retune * self.glide_step
+            };
+        }
+    }
+
+    /// Freeze the latest window of `input`. The loop this replaces moves
+    /// to `previous`.
+    fn capture(&mut self, input: &mut [RingBuffer], settings: &CaptureSettings) {
+        let window_size = settings.window_size;
+        self.window_size = window_size;
+        self.correction = window_size as f64 / settings.window_length;
+        self.spread = settings.spread;
+        self.xfade_countdown.fill(0);
+        self.retrigger_countdown.fill(0);
+        self.restart_countdown.fill(0);
+        std::mem::swap(&mut self.output, &mut self.previous);
+        // Not so far back that the window ends long before the note-on.
+        let search = if settings.align { window_size / 4 } else { 0 };
+        // Linked channels all move with the first one.
+        let back = window_size + settings.offset;
+        let linked_shift = input
+            .first()
+            .and_then(|input| input.crossing_before(back, search));
+        for (channel, (input, output)) in input.iter_mut().zip(self.output.iter_mut()).enumerate() {
+            let shift = if settings.linked {
+                linked_shift
+            } else {
+                input.crossing_before(back, search)
+            };
+            let shift = settings.offset + shift.unwrap_or(0);
+            // No further back than the recorded input reaches.
+            let haas = match channel {
+                1 => settings
+                    .haas
+                    .min(input.len().saturating_sub(window_size + shift)),
+                _ => 0,
+            };
+            input.open_window_back(window_size, shift + haas);
+            output.resize(window_size, 0.0);
+            match settings.seam {
+                SeamMode::Average => {
+                    for _ in 0..window_size {
+                        output.write(input.read());
+                    }
+                    output.smooth(settings.depth, settings.direction);
+                }
+                SeamMode::OverlapAdd => {
+                    output.capture_seam(input, settings.depth);
+                    for _ in 0..window_size {
+                        output.write(input.read());
+                    }
+                }
+            }
+            output.rewind(settings.direction);
+            // Unlike the Haas shift, the same window as the others, only
+            // played from further in.
+            if channel == 1 {
+                output.skip(self.spread);
+            }
+        }
+        self.grains = Default::default();
+        self.grain_playhead = self.output.first().map_or(0.0, RingBuffer::read_phase);
+        for (phase, output) in self.shimmer_phase.iter_mut().zip(&self.output) {
+            *phase = output.read_phase();
+        }
+        self.power = self.output.iter().map(RingBuffer::power).sum::<f64>()
+            / self.output.len().max(1) as f64;
+        self.grain_countdown = 0.0;
+    }
+
+    /// Next sample of the voice's frozen `channel`, blending `sample` into
+    /// the loop when held with feedback.
+    fn read(&mut self, channel: usize, sample: f64, playback: &Playback) -> f64 {
+        let output = &mut self.output[channel];
+        match playback.mode {
+            FreezeMode::Loop => {
+                if playback.feedback > 0.0 && self.note.is_some() {
+                    output.blend_at_read(sample, playback.feedback);
+                }
+                let stride = playback.stride * self.retune * self.correction;
+                let shimmer = self.shimmer(channel, stride, playback);
+                let mut frozen = self.read_loop(channel, stride, playback) + shimmer;
+                let restart = &mut self.restart_countdown[channel];
+                if *restart > 0 {
+                    frozen *= 1.0 - *restart as f64 / self.restart_length as f64;
+                    *restart -= 1;
+                }
+                let countdown = &mut self.retrigger_countdown[channel];
+                if *countdown == 0 {
+                    return frozen;
+                }
+                let alpha = *countdown as f64 / self.xfade_frames as f64;
+                *countdown -= 1;
+                let (fade, other) = playback.curve.gains(alpha);
+                let previous = &mut self.previous[channel];
+                fade * previous.read_directed(stride, playback.direction, playback.interpolation)
+                    + other * frozen
+            }
+            FreezeMode::Granular => {
+                let sum: f64 = self
+                    .grains
+                    .iter()
+                    .filter(|grain| grain.is_playing())
+                    .map(|grain| grain.envelope() * output.peek(grain.position))
+                    .sum();
+                self.grain_gain * sum
+            }
+        }
+    }
+
+    /// Next sample of the loop in `channel` read at `stride`, oversampled
+    /// by the factor `playback` asks for so faster reads don't alias. Read
+    /// as captured, there's nothing to alias and it isn't.
+    fn read_loop(&mut self, channel: usize, stride: f64, playback: &Playback) -> f64 {
+        let output = &mut self.output[channel];
+        let mut factor = if stride == 1.0 {
+            1
+        } else {
+            playback.oversampling
+        };
+        if factor != self.oversampling[channel] && self.retrigger_countdown[channel] > 0 {
+            // `previous` is busy fading out of the loop a re-trigger or
+            // stutter replaced, so the change waits for that to finish.
+            factor = self.oversampling[channel];
+        } else if factor != self.oversampling[channel] {
+            // The history holds samples at the old rate and the filter
+            // delays by a few frames, so fade over from a plain read of
+            // the loop where it is instead of jumping.
+            self.oversampling[channel] = factor;
+            self.decimators[channel] = Decimator::default();
+            self.previous[channel].copy_from(output);
+            self.retrigger_countdown[channel] = self.xfade_frames;
+        }
+        if factor == 1 {
+            return output.read_directed(stride, playback.direction, playback.interpolation);
+        }
+        let decimator = &mut self.decimators[channel];
+        for _ in 0..factor {
+            let stride = stride / factor as f64;
+            decimator.push(output.read_directed(
+                stride,
+                playback.direction,
+                playback.interpolation,
+            ));
+        }
+        decimator.output(decimation_kernel(factor))
+    }
+
+    /// Next sample of the octave up copy of the loop in `channel`, at its
+    /// level, blending it into the loop by the shimmer feedback while the
+    /// key is held.
+    fn shimmer(&mut self, channel: usize, stride: f64, playback: &Playback) -> f64 {
+        let output = &mut self.output[channel];
+        if output.is_empty() || (playback.shimmer_level == 0.0 && playback.shimmer_feedback == 0.0)
+        {
+            return 0.0;
+        }
+        let phase = &mut self.shimmer_phase[channel];
+        let shimmer = output.peek(*phase);
+        // The octave up copy doesn't bounce, it's too fast to hear it.
+        let step = match playback.direction {
+            PlayDirection::Forward | PlayDirection::PingPong => 2.0 * stride,
+            PlayDirection::Reverse => -2.0 * stride,
+        };
+        *phase = wrap_phase(*phase + step, output.len() as f64);
+        // Blending rather than adding keeps the loop within the levels it
+        // already has, however often it goes round.
+        if self.shimmer_blend > 0.0 && self.note.is_some() {
+            output.blend_at_read(shimmer, self.shimmer_blend);
+        }
+        playback.shimmer_level * shimmer
+    }
+
+    /// Age the playing grains by a frame and spawn a new one when it's due.
+    fn advance_grains(
+        &mut self,
+        playback: &Playback,
+        settings: &GrainSettings,
+        rng: &mut XorShift,
+    ) {
+        let len = self.window_size as f64;
+        // Grains scatter around the playhead anyway, so it needn't bounce.
+        let step = match playback.direction {
+            PlayDirection::Forward | PlayDirection::PingPong => playback.stride * self.retune,
+            PlayDirection::Reverse => -playback.stride * self.retune,
+        };
+        for grain in self.grains.iter_mut().filter(|grain| grain.is_playing()) {
+            grain.age += 1;
+            grain.position = wrap_phase(grain.position + step, len);
+        }
+        self.grain_playhead = wrap_phase(self.grain_playhead + step, len);
+        self.grain_countdown -= 1.0;
+        if self.grain_countdown <= 0.0 {
+            self.grain_countdown += settings.interval;
+            if let Some(grain) = self.grains.iter_mut().find(|grain| !grain.is_playing()) {
+                let offset = settings.spread * len * (rng.next_f64() - 0.5);
+                *grain = Grain {
+                    position: wrap_phase(self.grain_playhead + offset, len),
+                    age: 0,
+                    length: settings.length,
+                };
+            }
+        }
+        // Hann windows average to one half, so that's the level of the sum
+        // of as many of them as overlap.
+        let overlap = settings.length as f64 / settings.interval;
+        self.grain_gain = 1.0 / (0.5 * overlap).max(1.0);
+    }
+
+    /// Start fading the voice out; it becomes free once the fades complete,
+    /// or immediately if they're both zero.
+    fn release(&mut self, release: ReleaseTimes) {
+        self.note = None;
+        self.sustained = false;
+        self.layer = false;
+        // Fade out from wherever a note-on cross-fade got to.
+        for (envelope, countdown) in self.envelope.iter_mut().zip(&mut self.xfade_in_countdown) {
+            *envelope = envelope.min(1.0 - *countdown as f64 / self.xfade_frames as f64);
+            *countdown = 0;
+        }
+        self.xfade_countdown.fill(release.crossfade);
+        self.xfade_length = release.crossfade;
+        self.release_step = envelope_step(release.envelope);
+        self.release_level.copy_from_slice(&self.envelope);
+    }
+
+    /// Hold the loop again as if its key were back down, fading in from
+    /// wherever the release got to.
+    fn revive(&mut self) {
+        self.note = Some(self.pitch);
+        self.held_frames = 0;
+        for channel in 0..self.envelope.len() {
+            let countdown = &mut self.xfade_countdown[channel];
+            let crossfade = if *countdown > 0 {
+                *countdown as f64 / self.xfade_length as f64 * self.release_level[channel]
+            } else {
+                0.0
+            };
+            *countdown = 0;
+            let level = crossfade.max(self.envelope[channel]);
+            self.envelope[channel] = level;
+            self.xfade_in_countdown[channel] =
+                ((1.0 - level) * self.xfade_frames as f64).round() as usize;
+        }
+    }
+}
+
+/// How a note-on captures its window.
+#[derive(Clone, Copy)]
+struct CaptureSettings {
+    window_size: usize,
+    /// Exact length `window_size` is rounded from.
+    window_length: f64,
+    /// Length of the seam treatment in samples.
+    depth: usize,
+    direction: PlayDirection,
+    /// Start every channel's window at the same sample, rather than each at
+    /// its own zero crossing.
+    linked: bool,
+    /// Move windows back to start at an upward zero crossing.
+    align: bool,
+    /// Frames back from the latest input the window ends at.
+    offset: usize,
+    seam: SeamMode,
+    /// Frames the right channel's window starts earlier than the others.
+    haas: usize,
+    /// Frames into its window the right channel's loop starts.
+    spread: usize,
+}
+
+/// Note-on held back while its attack plays through.
+#[derive(Clone, Copy)]
+struct PendingNote {
+    pitch: u8,
+    velocity: u8,
+    /// See `FreezeEngine::trigger`.
+    length: Option<f64>,
+    /// Frames left until it freezes.
+    countdown: usize,
+}
+
+/// State of the trigger mode.
+#[derive(Clone, Copy)]
+enum AutoFreeze {
+    Idle,
+    /// The input got loud, and will be captured in this many frames.
+    Pending(usize),
+    /// Freezing the window of the given key.
+    Frozen(u8),
+}
+
+/// What the modwheel sweeps, from none at rest to all the way at full.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModTarget {
+    /// Slow the playback down, by two octaves at full wheel.
+    Speed,
+    /// Fade the frozen signal out.
+    Mix,
+    /// Close the filter, by `MOD_CUTOFF_OCTAVES` at full wheel.
+    Cutoff,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+}
+
+/// Which held key sounds in mono mode.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NotePriority {
+    Last,
+    Highest,
+    Lowest,
+}
+
+/// What a note-on captures its window from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaptureSource {
+    Input,
+    /// What's already frozen, transforming it further with every note.
+    FrozenOutput,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SeamMode {
+    /// Average the samples following the seam with their predecessors once
+    /// when capturing. That's a low-pass, dulling bright sources around the
+    /// seam the deeper it goes.
+    Average,
+    /// Cross-fade the end of the loop into the audio which led up to its
+    /// start, on every pass. Nothing is filtered, so the loop keeps its
+    /// highs, as long as the input reaches back far enough to overlap.
+    OverlapAdd,
+}
+
+/// Lengths in frames of the fades following a note-off.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ReleaseTimes {
+    /// Cross-fade from the frozen loop back to the live input.
+    crossfade: usize,
+    /// Release segment of the voice envelope.
+    envelope: usize,
+}
+
+impl ReleaseTimes {
+    const IMMEDIATE: ReleaseTimes = ReleaseTimes {
+        crossfade: 0,
+        envelope: 0,
+    };
+}
+
+/// Per frame change of an envelope segment lasting `frames`, jumping
+/// straight to its end when that's zero.
+fn envelope_step(frames: usize) -> f64 {
+    if frames == 0 {
+        f64::INFINITY
+    } else {
+        1.0 / frames as f64
+    }
+}
+
+/// Length in samples of one period of `freq`, transposed up by octaves
+/// while it doesn't fit into `max_window`, so the loop keeps its pitch class
+/// even for notes below `MIN_FREQ`.
+fn period_length(sample_rate: f64, freq: f64, max_window: usize) -> f64 {
+    let mut period = sample_rate / freq;
+    while period > max_window as f64 {
+        period /= 2.0;
+    }
+    period.clamp(1.0, max_window as f64)
+}
+
+/// Gain bringing a loop of mean square `power` the share `strength` of the
+/// way to the `loudness` of the input, in decibels, and within
+/// `MAX_MATCH_GAIN` either way. Silence on either side leaves it as it is.
+fn match_gain(power: f64, loudness: f64, strength: f64) -> f64 {
+    if strength == 0.0 || power < SILENCE_POWER || loudness < SILENCE_POWER {
+        return 1.0;
+    }
+    let gain = (loudness / power).sqrt().powf(strength);
+    gain.clamp(1.0 / MAX_MATCH_GAIN, MAX_MATCH_GAIN)
+}
+
+/// `data` if it's a well formed channel message, with the bytes past its
+/// length cleared. Messages relying on running status, system messages and
+/// those with a data byte out of range are `None`, as they can't be told
+/// apart from garbage.
+pub fn channel_message(data: [u8; 3]) -> Option<[u8; 3]> {
+    let length = match data[0] & 0xF0 {
+        0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => 3,
+        0xC0 | 0xD0 => 2,
+        _ => return None,
+    };
+    if data[1..length].iter().any(|&byte| byte >= 0x80) {
+        return None;
+    }
+    let mut message = [0; 3];
+    message[..length].copy_from_slice(&data[..length]);
+    Some(message)
+}
+
+/// Length of a period of `MIN_FREQ` at `sample_rate`.
+fn max_window_size(sample_rate: f64) -> usize {
+    (sample_rate / MIN_FREQ).ceil() as usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CrossfadeCurve {
+    Linear,
+    /// Keeps the summed power constant, so uncorrelated signals don't dip
+    /// in the middle of the fade.
+    EqualPower,
+}
+
+impl CrossfadeCurve {
+    /// Gains of the fading signal and of the one it fades against, at the
+    /// point `alpha` where 1 is fully the fading signal and 0 fully the other.
+    fn gains(self, alpha: f64) -> (f64, f64) {
+        match self {
+            CrossfadeCurve::Linear => (alpha, 1.0 - alpha),
+            CrossfadeCurve::EqualPower => {
+                let angle = alpha * FRAC_PI_2;
+                (angle.sin(), angle.cos())
+            }
+        }
+    }
+}
+
+/// One-pole high-pass filter removing DC offset below a few tens of Hz.
+#[derive(Clone, Copy, Default)]
+struct DcBlocker {
+    previous_input: f64,
+    previous_output: f64,
+}
+
+impl DcBlocker {
+    fn process(&mut self, sample: f64) -> f64 {
+        let output = sample - self.previous_input + DC_BLOCK_POLE * self.previous_output;
+        self.previous_input = sample;
+        self.previous_output = output;
+        output
+    }
+}
+
+/// Soft clipping of the output, which feedback and stacked voices can push
+/// past full scale.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Limiter {
+    /// Scale every channel by the gain the loudest one needs.
+    Linked,
+    /// Clip every channel on its own.
+    PerChannel,
+}
+
+impl Limiter {
+    fn apply(self, frame: &mut [f64], ceiling: f64) {
+        match self {
+            Limiter::Linked => {
+                let peak = frame
+                    .iter()
+                    .fold(0.0, |peak: f64, sample| peak.max(sample.abs()));
+                if peak > 0.0 {
+                    let gain = soft_clip(peak, ceiling) / peak;
+                    frame.iter_mut().for_each(|sample| *sample *= gain);
+                }
+            }
+            Limiter::PerChannel => {
+                for sample in frame.iter_mut() {
+                    *sample = soft_clip(*sample, ceiling);
+                }
+            }
+        }
+    }
+}
+
+/// Untouched up to half of `ceiling`, then bending smoothly towards it with
+/// a `tanh` curve meeting the straight part at the same slope.
+fn soft_clip(sample: f64, ceiling: f64) -> f64 {
+    let knee = 0.5 * ceiling;
+    let magnitude = sample.abs();
+    if magnitude <= knee {
+        return sample;
+    }
+    let clipped = knee + knee * ((magnitude - knee) / knee).tanh();
+    clipped.copysign(sample)
+}
+
+/// History of an oversampled signal, filtered down to one sample per frame.
+#[derive(Clone, Copy)]
+struct Decimator {
+    history: [f64; DECIMATOR_TAPS_PER_FACTOR * MAX_OVERSAMPLING],
+    /// Index of the latest sample in `history`.
+    latest: usize,
+}
+
+impl Default for Decimator {
+    fn default() -> Self {
+        Decimator {
+            history: [0.0; DECIMATOR_TAPS_PER_FACTOR * MAX_OVERSAMPLING],
+            latest: 0,
+        }
+    }
+}
+
+impl Decimator {
+    fn push(&mut self, sample: f64) {
+        self.latest = (self.latest + 1) % self.history.len();
+        self.history[self.latest] = sample;
+    }
+
+    /// The latest samples filtered by `kernel`, no longer than the history.
+    fn output(&self, kernel: &[f64]) -> f64 {
+        let len = self.history.len();
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(age, tap)| tap * self.history[(self.latest + len - age) % len])
+            .sum()
+    }
+}
+
+/// Low-pass filter taps for decimating by `factor`, 2 or 4: a Blackman
+/// windowed sinc cutting off a little below the Nyquist frequency of the
+/// decimated rate, with a gain of one.
+fn decimation_kernel(factor: usize) -> &'static [f64] {
+    static KERNELS: [OnceLock<Vec<f64>>; 2] = [OnceLock::new(), OnceLock::new()];
+    let kernel = &KERNELS[usize::from(factor > 2)];
+    kernel.get_or_init(|| {
+        let len = DECIMATOR_TAPS_PER_FACTOR * factor;
+        let cutoff = 0.45 / factor as f64;
+        let centre = (len - 1) as f64 / 2.0;
+        let taps: Vec<f64> = (0..len)
+            .map(|n| {
+                let x = n as f64 - centre;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (TAU * cutoff * x).sin() / (PI * x)
+                };
+                let phase = TAU * n as f64 / (len - 1) as f64;
+                sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+            })
+            .collect();
+        let sum: f64 = taps.iter().sum();
+        taps.iter().map(|tap| tap / sum).collect()
+    })
+}
+
+/// Bit depth and sample rate reduction.
+#[derive(Clone, Copy, Default)]
+struct Degrader {
+    held: f64,
+    /// Frames until the next sample is taken.
+    countdown: usize,
+}
+
+impl Degrader {
+    fn process(&mut self, sample: f64, playback: &Playback) -> f64 {
+        if self.countdown == 0 {
+            self.countdown = playback.hold;
+            self.held = match playback.bits {
+                Some(bits) => {
+                    let steps = (bits - 1.0).exp2();
+                    (sample * steps).round() / steps
+                }
+                None => sample,
+            };
+        }
+        self.countdown -= 1;
+        self.held
+    }
+}
+
+/// Coefficients of the state-variable filter for the current frame.
+#[derive(Clone, Copy)]
+struct FilterSettings {
+    kind: FilterKind,
+    /// Prewarped integrator gain.
+    g: f64,
+    /// Damping, the inverse of the quality factor.
+    k: f64,
+}
+
+impl FilterSettings {
+    fn new(kind: FilterKind, cutoff: f64, q: f64, sample_rate: f64) -> Self {
+        // The trapezoidal filter is stable at any cutoff below Nyquist, but
+        // the prewarping tangent blows up towards it.
+        let cutoff = cutoff.min(0.49 * sample_rate);
+        FilterSettings {
+            kind,
+            g: (PI * cutoff / sample_rate).tan(),
+            k: 1.0 / q,
+        }
+    }
+}
+
+/// Topology-preserving state-variable filter, as described by Zavalishin
+/// in "The Art of VA Filter Design".
+#[derive(Clone, Copy, Default)]
+struct Filter {
+    /// Integrator states.
+    band: f64,
+    low: f64,
+}
+
+impl Filter {
+    fn process(&mut self, sample: f64, settings: &FilterSettings) -> f64 {
+        let FilterSettings { kind, g, k } = *settings;
+        let high = (sample - (k + g) * self.band - self.low) / (1.0 + g * (g + k));
+        let band = g * high + self.band;
+        let low = g * band + self.low;
+        self.band = g * high + band;
+        self.low = g * band + low;
+        match kind {
+            FilterKind::LowPass => low,
+            FilterKind::HighPass => high,
+        }
+    }
+}
+
+/// Placement of the frozen signal in the stereo field.
+#[derive(Clone, Copy)]
+struct Stereo {
+    /// Gains of the left and right channels, both 1 in the centre.
+    left: f64,
+    right: f64,
+    /// Side level relative to the mid, 1 leaving the image as it is.
+    width: f64,
+}
+
+impl Stereo {
+    /// From `pan` between -1 (left) and 1 (right) with a constant power
+    /// law, and mid/side `width`.
+    fn new(pan: f64, width: f64) -> Self {
+        let angle = (pan + 1.0) * FRAC_PI_4;
+        Stereo {
+            left: SQRT_2 * angle.cos(),
+            right: SQRT_2 * angle.sin(),
+            width,
+        }
+    }
+
+    fn apply(&self, left: &mut f64, right: &mut f64) {
+        let mid = 0.5 * (*left + *right);
+        let side = 0.5 * (*left - *right) * self.width;
+        *left = self.left * (mid + side);
+        *right = self.right * (mid - side);
+    }
+}
+
+/// One-pole glide of a control towards where it's set, so that automation
+/// steps and fast sweeps don't click.
+#[derive(Clone, Copy)]
+struct Smoother(f64);
+
+impl Smoother {
+    /// Move a frame's worth towards `target`, by `coefficient` of the way.
+    fn advance(&mut self, target: f64, coefficient: f64) -> f64 {
+        self.0 += coefficient * (target - self.0);
+        // Settle exactly, so a centred speed knob plays the loop as
+        // captured, for one.
+        if (target - self.0).abs() < 1e-9 {
+            self.0 = target;
+        }
+        self.0
+    }
+}
+
+/// Continuous parameters smoothed before use. Switches and choices take
+/// effect right away instead.
+struct Controls {
+    mix: Smoother,
+    input_gain: Smoother,
+    gain: Smoother,
+    feedback: Smoother,
+    shimmer_level: Smoother,
+    shimmer_feedback: Smoother,
+    pan: Smoother,
+    width: Smoother,
+    /// In octaves above 1 Hz.
+    cutoff: Smoother,
+    resonance: Smoother,
+    ceiling: Smoother,
+}
+
+impl Controls {
+    /// Starting out settled where `params` are.
+    fn new(params: &ZamerzikaParameters) -> Self {
+        Controls {
+            mix: Smoother(f64::from(params.get(Parameter::Mix))),
+            input_gain: Smoother(params.input_gain()),
+            gain: Smoother(params.gain()),
+            feedback: Smoother(params.feedback()),
+            shimmer_level: Smoother(params.shimmer_level()),
+            shimmer_feedback: Smoother(params.shimmer_feedback()),
+            pan: Smoother(params.pan()),
+            width: Smoother(params.width()),
+            cutoff: Smoother(params.cutoff().log2()),
+            resonance: Smoother(params.resonance()),
+            ceiling: Smoother(params.ceiling()),
+        }
+    }
+}
+
+/// Settings shared by every voice and channel for the current frame.
+#[derive(Clone, Copy)]
+struct Playback {
+    /// Read speed through the captured window, 1 playing it as captured.
+    stride: f64,
+    /// Share of the input replaced by the frozen signal.
+    mix: f64,
+    /// Only the frozen signal plays, whatever the mix.
+    solo: bool,
+    direction: PlayDirection,
+    curve: CrossfadeCurve,
+    feedback: f64,
+    /// Level the stutter lets the loops through at.
+    stutter_gate: f64,
+    /// Level of the octave up copy of the loop, and the share of the loop
+    /// it replaces per second.
+    shimmer_level: f64,
+    shimmer_feedback: f64,
+    /// Loop reads per frame, filtered back down to one.
+    oversampling: usize,
+    interpolation: Interpolation,
+    mode: FreezeMode,
+    /// Resolution the frozen signal is quantized to, `None` leaving it be.
+    bits: Option<f64>,
+    /// Frames every frozen sample is held for.
+    hold: usize,
+    stereo: Stereo,
+    /// Boost of the frozen level at full aftertouch, 1 doubling it.
+    pressure_depth: f64,
+    filter: Option<FilterSettings>,
+    /// Input trim, output level and limiter ceiling as amplitudes.
+    input_gain: f64,
+    gain: f64,
+    ceiling: f64,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            stride: 1.0,
+            mix: 1.0,
+            solo: false,
+            direction: PlayDirection::Forward,
+            curve: CrossfadeCurve::Linear,
+            feedback: 0.0,
+            stutter_gate: 1.0,
+            shimmer_level: 0.0,
+            shimmer_feedback: 0.0,
+            oversampling: 1,
+            interpolation: Interpolation::Linear,
+            mode: FreezeMode::Loop,
+            bits: None,
+            hold: 1,
+            stereo: Stereo::new(0.0, 1.0),
+            pressure_depth: 0.0,
+            filter: None,
+            input_gain: 1.0,
+            gain: 1.0,
+            ceiling: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FreezeMode {
+    /// Play the captured window over and over.
+    Loop,
+    /// Scatter Hann-windowed grains from around a playhead moving through
+    /// the captured window.
+    Granular,
+}
+
+#[derive(Clone, Copy)]
+struct GrainSettings {
+    /// Grain length in frames.
+    length: usize,
+    /// Frames between grain onsets.
+    interval: f64,
+    /// Range of random grain offsets around the playhead, as a share of
+    /// the window.
+    spread: f64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Grain {
+    /// Fractional read position in the captured window.
+    position: f64,
+    age: usize,
+    length: usize,
+}
+
+impl Grain {
+    fn is_playing(&self) -> bool {
+        self.age < self.length
+    }
+
+    fn envelope(&self) -> f64 {
+        let phase = self.age as f64 / self.length as f64;
+        0.5 * (1.0 - (2.0 * PI * phase).cos())
+    }
+}
+
+/// Small and fast xorshift64* generator, plenty for scattering grains.
+struct XorShift(u64);
+
+impl XorShift {
+    /// Generator starting from `seed`, none of which leave it stuck at zero.
+    fn new(seed: u32) -> Self {
+        XorShift(DEFAULT_SEED ^ u64::from(seed).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// One `len` buffer per channel, allocated up front so that capturing never
+/// allocates on the audio thread.
+fn ring_buffers(channels: usize, len: usize) -> Vec<RingBuffer> {
+    (0..channels).map(|_| RingBuffer::new(len)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+    /// A4 on and off.
+    const NOTE_ON: [u8; 3] = [0x90, 69, 127];
+    const NOTE_OFF: [u8; 3] = [0x80, 69, 64];
+
+    fn params() -> Arc<ZamerzikaParameters> {
+        Arc::new(ZamerzikaParameters::default())
+    }
+
+    fn sine(freq: f64, frame: usize) -> f64 {
+        (TAU * freq * frame as f64 / RATE).sin()
+    }
+
+    fn rms(frames: &[[f64; CHANNELS]]) -> f64 {
+        let sum: f64 = frames.iter().flatten().map(|sample| sample * sample).sum();
+        (sum / (CHANNELS * frames.len()).max(1) as f64).sqrt()
+    }
+
+    /// Half a second of A4 followed by as much silence.
+    fn tone_then_silence() -> Vec<[f64; CHANNELS]> {
+        (0..RATE as usize)
+            .map(|frame| {
+                [if frame < 24_000 {
+                    sine(440.0, frame)
+                } else {
+                    0.0
+                }; CHANNELS]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_holds_the_tone_through_the_silence_until_note_off() {
+        let input = tone_then_silence();
+        let events = [(12_000, NOTE_ON), (36_000, NOTE_OFF)];
+        let output = render(params(), &input, &events, RATE);
+        // Untouched up to the note-on, then the loop of the tone carries
+        // on into the silence, and once released it's the silence again
+        // but for the DC blocker ringing out.
+        assert_eq!(output[..12_000], input[..12_000]);
+        assert!(rms(&output[25_000..36_000]) > 0.5);
+        assert!(output[40_000..]
+            .iter()
+            .flatten()
+            .all(|&sample| sample.abs() < 1e-4));
+    }
+
+    #[test]
+    fn processing_before_any_midi_passes_the_input_through() {
+        let input = tone_then_silence();
+        assert_eq!(render(params(), &input, &[], RATE), input);
+        // Nor does anything releasing what was never held.
+        assert_eq!(render(params(), &input, &[(0, NOTE_OFF)], RATE), input);
+    }
+
+    #[test]
+    fn reset_starts_over_silent_and_unfrozen() {
+        let mut engine = FreezeEngine::new(params(), CHANNELS, FreezeConfig::default());
+        for (frame, samples) in tone_then_silence().iter().enumerate().take(30_000) {
+            if frame == 12_000 {
+                engine.process_midi(NOTE_ON);
+            }
+            engine.process_frame(&mut { *samples });
+        }
+        assert!(engine.is_frozen());
+        engine.reset();
+        assert!(!engine.is_frozen());
+        // Silence in is silence out, with nothing of the old loop or input.
+        for _ in 0..12_000 {
+            let mut samples = [0.0; CHANNELS];
+            engine.process_frame(&mut samples);
+            assert_eq!(samples, [0.0; CHANNELS]);
+        }
+        // And the next note freezes what's come in since, at its level
+        // rather than the old loop's.
+        for frame in 0..12_000 {
+            let mut samples = [0.5 * sine(440.0, frame); CHANNELS];
+            engine.process_frame(&mut samples);
+            assert_eq!(samples, [0.5 * sine(440.0, frame); CHANNELS]);
+        }
+        engine.process_midi(NOTE_ON);
+        let output: Vec<_> = (0..12_000)
+            .map(|_| {
+                let mut samples = [0.0; CHANNELS];
+                engine.process_frame(&mut samples);
+                samples
+            })
+            .collect();
+        let level = rms(&output[6_000..]);
+        assert!(level > 0.25 && level < 0.5, "{level}");
+    }
+
+    #[test]
+    fn channel_message_keeps_well_formed_messages_only() {
+        assert_eq!(channel_message(NOTE_ON), Some(NOTE_ON));
+        // The byte a two byte message leaves over is cleared.
+        assert_eq!(channel_message([0xC3, 5, 99]), Some([0xC3, 5, 0]));
+        assert_eq!(channel_message([0xD0, 64, 0x90]), Some([0xD0, 64, 0]));
+        // Cut short by the next status byte.
+        assert_eq!(channel_message([0x90, 69, 0x80]), None);
+        assert_eq!(channel_message([0xB0, 0xF8, 0]), None);
+        // Running status, with the status byte left out.
+        assert_eq!(channel_message([69, 127, 0]), None);
+        // System messages.
+        for status in [0xF0, 0xF2, 0xF8, 0xFF] {
+            assert_eq!(channel_message([status, 69, 127]), None);
+        }
+    }
+
+    #[test]
+    fn malformed_messages_freeze_nothing() {
+        let input = tone_then_silence();
+        let events = [
+            (12_000, [69, 127, 0]),
+            (12_000, [0x90, 69, 0xF8]),
+            (12_000, [0xF0, 0x90, 69]),
+        ];
+        assert_eq!(render(params(), &input, &events, RATE), input);
+    }
+
+    #[test]
+    fn zero_velocity_note_on_releases_like_a_note_off() {
+        let input = tone_then_silence();
+        let zero_velocity = [(12_000, NOTE_ON), (36_000, [0x90, 69, 0])];
+        let output = render(params(), &input, &zero_velocity, RATE);
+        assert!(rms(&output[25_000..36_000]) > 0.5);
+        assert!(output[40_000..]
+            .iter()
+            .flatten()
+            .all(|&sample| sample.abs() < 1e-4));
+        // Neither any other sort of release nor a fresh freeze of silence.
+        let note_off = [
+            (12_000, NOTE_ON),
+            (36_000, [0x80, 69, DEFAULT_RELEASE_VELOCITY]),
+        ];
+        assert_eq!(output, render(params(), &input, &note_off, RATE));
+    }
+
+    /// Largest difference between consecutive samples of `frames`.
+    fn max_step(frames: &[[f64; CHANNELS]]) -> f64 {
+        frames
+            .windows(2)
+            .flat_map(|pair| {
+                (0..CHANNELS).map(move |channel| (pair[1][channel] - pair[0][channel]).abs())
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn sweeping_the_speed_while_frozen_does_not_jump() {
+        let params = params();
+        let mut engine = FreezeEngine::new(Arc::clone(&params), CHANNELS, FreezeConfig::default());
+        let mut output = Vec::new();
+        for frame in 0..48_000 {
+            if frame == 12_000 {
+                engine.process_midi(NOTE_ON);
+            }
+            // From as captured up to four times as fast and back, moved at
+            // the pace of a host's blocks.
+            if (12_000..44_000).contains(&frame) && frame % 64 == 0 {
+                let sweep = 1.0 - ((frame as f32 - 28_000.0) / 16_000.0).abs();
+                params.set(Parameter::Speed, 0.5 + 0.5 * sweep);
+            }
+            let mut samples = [0.5 * sine(440.0, frame); CHANNELS];
+            engine.process_frame(&mut samples);
+            output.push(samples);
+        }
+        // A4 four times as fast at that level changes by up to 0.115 from
+        // one sample to the next, while the read position jumping would
+        // typically make it several times that.
+        assert!(max_step(&output[12_100..44_000]) < 0.15);
+    }
+
+    #[test]
+    fn chord_captures_for_its_lowest_key_whatever_the_order() {
+        let params = params();
+        params.set(Parameter::Chord, 1.0);
+        let mut engine = FreezeEngine::new(params, CHANNELS, FreezeConfig::default());
+        for frame in 0..12_000 {
+            engine.process_frame(&mut [sine(440.0, frame); CHANNELS]);
+        }
+        for pitch in [76, 60, 67] {
+            engine.process_midi([0x90, pitch, 100]);
+        }
+        engine.process_frame(&mut [0.0; CHANNELS]);
+        let held: Vec<_> = engine
+            .voices
+            .iter()
+            .filter(|voice| voice.note.is_some())
+            .collect();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].note, Some(60));
+        let expected = engine.window_size(engine.params.note_freq(60));
+        assert_eq!(held[0].window_size, expected);
+    }
+
+    #[test]
+    fn lowest_note_freezes_at_high_rates() {
+        for rate in [96_000.0, 192_000.0] {
+            let mut engine = FreezeEngine::new(params(), CHANNELS, FreezeConfig::default());
+            engine.set_sample_rate(rate);
+            let frames = rate as usize;
+            let tone = |frame| [(TAU * 440.0 * frame as f64 / rate).sin(); CHANNELS];
+            for frame in 0..frames {
+                engine.process_frame(&mut tone(frame));
+            }
+            engine.process_midi([0x90, 0, 127]);
+            let mut output = Vec::new();
+            for _ in 0..frames {
+                let mut samples = [0.0; CHANNELS];
+                engine.process_frame(&mut samples);
+                output.push(samples);
+            }
+            // A whole period of the note, which the buffers have room for.
+            let voice = engine.voices.iter().find(|voice| voice.note == Some(0));
+            let window_size = voice.map(|voice| voice.window_size);
+            assert_eq!(
+                window_size,
+                Some((rate / engine.params.note_freq(0)).round() as usize)
+            );
+            assert!(window_size.unwrap() <= engine.max_window);
+            assert!(engine.is_frozen());
+            assert!(rms(&output[frames / 2..]) > 0.5, "{rate}");
+        }
+    }
+
+    #[test]
+    fn frozen_loop_repeats_at_the_exact_period_of_the_note() {
+        let params = params();
+        // E7, whose period of 18.2 samples rounds to a window 1% short.
+        let freq = params.note_freq(100);
+        let input: Vec<_> = (0..36_000)
+            .map(|frame| {
+                [if frame < 12_000 {
+                    sine(freq, frame)
+                } else {
+                    0.0
+                }; CHANNELS]
+            })
+            .collect();
+        let output = render(params, &input, &[(12_000, [0x90, 100, 127])], RATE);
+        // Upward zero crossings, placed between samples by interpolation.
+        let crossings: Vec<f64> = (14_000..36_000)
+            .filter(|&frame| output[frame - 1][0] < 0.0 && output[frame][0] >= 0.0)
+            .map(|frame| {
+                let (before, after) = (output[frame - 1][0], output[frame][0]);
+                frame as f64 - after / (after - before)
+            })
+            .collect();
+        let period = (crossings[crossings.len() - 1] - crossings[0]) / (crossings.len() - 1) as f64;
+        let expected = RATE / freq;
+        // Where looping the 18 samples of the window would be 1% off.
+        assert!(
+            (period - expected).abs() < 1e-5 * expected,
+            "{period} vs {expected}"
+        );
+    }
+
+    /// Energy of the changes from one sample to the next relative to that of
+    /// the samples, 2 for white noise and falling as it's lowpassed.
+    fn brightness(frames: &[[f64; CHANNELS]]) -> f64 {
+        let steps: f64 = frames
+            .windows(2)
+            .flat_map(|pair| (0..CHANNELS).map(move |channel| pair[1][channel] - pair[0][channel]))
+            .map(|step| step * step)
+            .sum();
+        let energy: f64 = frames.iter().flatten().map(|sample| sample * sample).sum();
+        steps / energy
+    }
+
+    /// The loop of white noise frozen with `seam`, as heard once the attack
+    /// is over.
+    fn frozen_noise(seam: SeamMode) -> Vec<[f64; CHANNELS]> {
+        let params = params();
+        params.set(Parameter::Smoothing, 1.0);
+        let seam = match seam {
+            SeamMode::Average => 0.0,
+            SeamMode::OverlapAdd => 1.0,
+        };
+        params.set(Parameter::Seam, seam);
+        let mut rng = XorShift::new(1);
+        let input: Vec<_> = (0..24_000)
+            .map(|frame| {
+                [if frame < 12_000 {
+                    rng.next_f64() - 0.5
+                } else {
+                    0.0
+                }; CHANNELS]
+            })
+            .collect();
+        render(params, &input, &[(12_000, NOTE_ON)], RATE).split_off(14_000)
+    }
+
+    #[test]
+    fn overlap_add_seam_keeps_more_of_the_highs_than_averaging() {
+        let average = brightness(&frozen_noise(SeamMode::Average));
+        let overlap = brightness(&frozen_noise(SeamMode::OverlapAdd));
+        // Noise stays about as bright across an overlapped seam, around 1.6,
+        // where repeatedly averaging dulls the whole loop to under half.
+        assert!(overlap > 1.2, "{overlap}");
+        assert!(overlap > 2.0 * average, "{overlap} vs {average}");
+    }
+
+    #[test]
+    fn render_follows_parameters_set_without_a_host() {
+        let input = tone_then_silence();
+        let events = [(12_000, NOTE_ON), (36_000, NOTE_OFF)];
+        let params = params();
+        params.set(Parameter::Mix, 0.0);
+        // All live, so nothing is heard of the freeze.
+        assert_eq!(render(params, &input, &events, RATE), input);
+    }
+
+    #[test]
+    fn synced_stutter_gates_at_the_division_without_a_rate() {
+        let params = params();
+        params.set(Parameter::StutterSync, 1.0);
+        // A quarter note, half a second at 120 BPM.
+        params.set(Parameter::StutterDivision, 2.0 / 8.0);
+        let mut engine = FreezeEngine::new(params, CHANNELS, FreezeConfig::default());
+        engine.set_sample_rate(RATE);
+        engine.set_tempo(Some(120.0));
+        let output: Vec<_> = (0..60_000)
+            .map(|frame| {
+                if frame == 12_000 {
+                    engine.process_midi(NOTE_ON);
+                }
+                let mut samples = [if frame < 12_000 {
+                    sine(440.0, frame)
+                } else {
+                    0.0
+                }; CHANNELS];
+                engine.process_frame(&mut samples);
+                samples
+            })
+            .collect();
+        let levels: Vec<f64> = output[12_000..].chunks(1_000).map(rms).collect();
+        let loudest = levels.iter().copied().fold(0.0, f64::max);
+        let quietest = levels.iter().copied().fold(f64::INFINITY, f64::min);
+        assert!(loudest > 0.25, "{loudest}");
+        assert!(quietest < 0.01, "{quietest}");
+    }
+
+    #[test]
+    fn loops_read_as_captured_skip_oversampling() {
+        let mut voice = Voice::new(1, 64, 16);
+        for frame in 0..64 {
+            voice.output[0].write(frame as f64);
+        }
+        let playback = Playback {
+            oversampling: 4,
+            ..Playback::default()
+        };
+        let read: Vec<f64> = (0..64)
+            .map(|_| voice.read_loop(0, 1.0, &playback))
+            .collect();
+        // Straight out of the loop, without the decimator's delay.
+        assert_eq!(read, (0..64).map(f64::from).collect::<Vec<_>>());
+        assert_eq!(voice.oversampling[0], 1);
+        voice.read_loop(0, 1.5, &playback);
+        assert_eq!(voice.oversampling[0], 4);
+    }
+
+    #[test]
+    fn stutter_restarts_fade_the_loop_back_in_and_leave_a_re_trigger_fading() {
+        let mut voice = Voice::new(1, 64, 16);
+        for _ in 0..64 {
+            voice.output[0].write(1.0);
+        }
+        voice.retrigger_countdown[0] = 8;
+        voice.restart(PlayDirection::Forward, 4);
+        assert_eq!(voice.retrigger_countdown[0], 8);
+        assert_eq!(voice.previous[0].len(), 64);
+        assert_eq!(voice.previous[0].peek(0.0), 0.0);
+        voice.retrigger_countdown[0] = 0;
+        let playback = Playback::default();
+        let read: Vec<f64> = (0..6).map(|_| voice.read(0, 0.0, &playback)).collect();
+        assert_eq!(read, [0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn offline_renders_repeat_with_the_seed() {
+        let input = tone_then_silence();
+        let events = [(12_000, NOTE_ON), (36_000, NOTE_OFF)];
+        let granular = |seed: f32| {
+            let params = params();
+            params.set(Parameter::Mode, 1.0);
+            params.set(Parameter::GrainSpread, 1.0);
+            params.set(Parameter::Seed, seed);
+            render(params, &input, &events, RATE)
+        };
+        let first = granular(0.0);
+        assert!(rms(&first[24_000..36_000]) > 0.1);
+        assert_eq!(granular(0.0), first);
+        assert_ne!(granular(0.5), first);
+    }
+
+    #[test]
+    fn soloing_brings_back_the_loop_last_released() {
+        let params = params();
+        let mut engine = FreezeEngine::new(params.clone(), CHANNELS, FreezeConfig::default());
+        engine.set_sample_rate(RATE);
+        let input = tone_then_silence();
+        // The tone, then silence for as long as it takes.
+        let run = |engine: &mut FreezeEngine, frames: std::ops::Range<usize>| {
+            frames
+                .map(|frame| {
+                    let mut samples = input.get(frame).copied().unwrap_or([0.0; CHANNELS]);
+                    engine.process_frame(&mut samples);
+                    samples
+                })
+                .collect::<Vec<_>>()
+        };
+        run(&mut engine, 0..12_000);
+        engine.process_midi(NOTE_ON);
+        run(&mut engine, 12_000..24_000);
+        engine.process_midi(NOTE_OFF);
+        let released = run(&mut engine, 24_000..48_000);
+        assert!(rms(&released[12_000..]) < 1e-3);
+        params.set(Parameter::SoloWet, 1.0);
+        let soloed = run(&mut engine, 48_000..60_000);
+        assert!(rms(&soloed[2_000..]) > 0.25, "{}", rms(&soloed[2_000..]));
+        assert!(max_step(&soloed) < 0.2, "{}", max_step(&soloed));
+        params.set(Parameter::SoloWet, 0.0);
+        let unsoloed = run(&mut engine, 60_000..72_000);
+        assert!(rms(&unsoloed[6_000..]) < 1e-3);
+    }
+}
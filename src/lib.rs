@@ -1,209 +1,2349 @@
 #[macro_use]
 extern crate vst;
 
+#[cfg(feature = "clap")]
+mod clap;
+pub mod engine;
+pub mod ring;
+pub mod tuning;
+
+use std::{
+    f64::consts::FRAC_1_SQRT_2,
+    mem::take,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+};
+
 use vst::{
-    api::{Events, Supported},
-    buffer::AudioBuffer,
-    event::Event,
-    plugin::{CanDo, Category, HostCallback, Info, Plugin},
+    api::{Events, Supported, TimeInfoFlags},
+    buffer::{AudioBuffer, SendEventBuffer},
+    event::{Event, MidiEvent},
+    host::Host,
+    plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters},
+    util::AtomicFloat,
 };
 
+use engine::{
+    CaptureSource, CrossfadeCurve, FilterKind, FreezeConfig, FreezeEngine, FreezeMode, Limiter,
+    ModTarget, NotePriority, SeamMode,
+};
+use ring::{Interpolation, PlayDirection};
+use tuning::TuningTable;
+
 /// Stereo should be enough for everyone ™
-const CHANNELS: usize = 2;
-/// MIDI Note 0 is ~8.176 Hz, and assuming max sample rate to be 96 kHz
-/// that would correspond to ~11742 samples.
-const MAX_WINDOW_SIZE: usize = 11742;
+/// Used as the bus width advertised to the host until it processes with
+/// another, everything else follows `FreezeEngine::channels`.
+pub const CHANNELS: usize = 2;
 // Used to smooth out freezed loop, reducing saw component in the output,
 // as well as to cross-fade on note off, reducing clicks.
+// The default cross-fade and smoothing parameters reproduce this length
+// at 48 kHz.
 const XFADE_FRAMES: usize = 64;
+/// Upper end of the cross-fade length parameter.
+const MAX_XFADE_MS: f32 = 100.0;
+/// Bit depth of the lo-fi stage at which it stops quantizing.
+const MAX_BITS: f64 = 24.0;
+/// Strongest sample rate reduction of the lo-fi stage.
+const MAX_DOWNSAMPLE: usize = 32;
+/// Upper end of the attack, release and glide parameters.
+const MAX_ENVELOPE_MS: f32 = 2000.0;
+/// Upper end of the seam smoothing parameter, which also defaults to the
+/// length of `XFADE_FRAMES` at 48 kHz.
+const MAX_SMOOTH_MS: f32 = 20.0;
+/// Preallocated room for MIDI events per block, so a busy controller
+/// doesn't cause allocations on the audio thread.
+const MAX_PENDING_EVENTS: usize = 512;
+/// Feedback blends rather than adds live input into the loop, so it can't
+/// grow louder than its sources; staying below 1 keeps some of the loop.
+const MAX_FEEDBACK: f64 = 0.9;
+/// Longest delay of the right channel's loop for widening mono sources.
+const MAX_HAAS_MS: f32 = 30.0;
+/// Furthest the right channel's loop starts into the window, in
+/// milliseconds.
+const MAX_CHANNEL_SPREAD_MS: f32 = 5.0;
+/// Longest attack hold, in milliseconds.
+const MAX_ATTACK_HOLD_MS: f32 = 500.0;
+/// Longest window the trigger mode can be set to, in milliseconds.
+const MAX_TRIGGER_WINDOW_MS: f32 = 2000.0;
+/// Upper end of the freeze length cap in seconds.
+const MAX_HOLD_SECONDS: f32 = 60.0;
+/// Share of the loop its octave up copy replaces per second at most.
+const MAX_SHIMMER_FEEDBACK: f64 = 0.5;
+/// Furthest the input envelope opens the filter, in octaves.
+const MAX_ENVELOPE_OCTAVES: f64 = 6.0;
+/// Number of distinct random seeds.
+const SEEDS: usize = 1000;
+/// Widest pitch-bend range in semitones, two octaves either way.
+const MAX_BEND_RANGE: usize = 24;
+/// Frozen loops can be transposed this many octaves either way.
+const MAX_OCTAVE_SHIFT: i32 = 2;
+/// Algorithmic latency in samples, reported for host delay compensation.
+/// Freezing reads already captured audio without look-ahead, hence none.
+const LATENCY: usize = 0;
+
+// Customized builds can set these at compile time to tell themselves apart
+// from this one, and keep hosts from mistaking one for the other by id.
+const NAME: &str = match option_env!("ZAMERZIKA_NAME") {
+    Some(name) => name,
+    None => "Zamerzika",
+};
+const VENDOR: &str = match option_env!("ZAMERZIKA_VENDOR") {
+    Some(vendor) => vendor,
+    None => "Ruslan Prakapchuk",
+};
+const UNIQUE_ID: i32 = match option_env!("ZAMERZIKA_UNIQUE_ID") {
+    Some(id) => parse_decimal(id),
+    None => 1_804_198_802,
+};
+/// Crate version in the four digit VST convention, 1.2.3 being 1230, which
+/// leaves a single digit for the minor and patch numbers.
+const VERSION: i32 = 1000 * parse_decimal(env!("CARGO_PKG_VERSION_MAJOR"))
+    + 100 * parse_decimal(env!("CARGO_PKG_VERSION_MINOR"))
+    + 10 * parse_decimal(env!("CARGO_PKG_VERSION_PATCH"));
 
+/// Value of a non-negative decimal number, at compile time, failing the
+/// build on anything else.
+const fn parse_decimal(digits: &str) -> i32 {
+    let digits = digits.as_bytes();
+    let mut value: i32 = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "not a decimal number");
+        value = 10 * value + (digits[i] - b'0') as i32;
+        i += 1;
+    }
+    value
+}
+
+/// VST adapter around `FreezeEngine`.
 struct Zamerzika {
-    sample_rate: f64,
-    note: Option<u8>,
-    input: [RingBuffer; CHANNELS],
-    output: [RingBuffer; CHANNELS],
-    window_size: usize,
-    xfade_countdown: [usize; CHANNELS],
+    host: HostCallback,
+    params: Arc<ZamerzikaParameters>,
+    engine: FreezeEngine,
+    /// MIDI events received for the upcoming block, in host order.
+    pending_events: Vec<MidiEvent>,
+    /// Index of the first event in `pending_events` not yet applied.
+    next_event: usize,
+    /// Notes to echo to the host at the end of the block, see `MidiThru`.
+    thru_events: Vec<MidiEvent>,
+    send_buffer: SendEventBuffer,
+    /// Frame being processed and the one captured from, as wide as the
+    /// engine.
+    frame: Vec<f64>,
+    source: Vec<f64>,
+    /// Output bus width the host last processed with. VST 2 hosts don't
+    /// otherwise say, so `resume` makes it the engine's when it differs.
+    bus_width: usize,
 }
 
 impl Zamerzika {
-    fn process_sample(&mut self, channel: usize, sample: f64) -> f64 {
-        self.input[channel].write(sample);
-        if self.note.is_some() {
-            self.output[channel].read()
-        } else if self.xfade_countdown[channel] > 0 {
-            let alpha = self.xfade_countdown[channel] as f64 / XFADE_FRAMES as f64;
-            let mix = alpha * self.output[channel].read() + (1.0 - alpha) * sample;
-            self.xfade_countdown[channel] -= 1;
-            mix
+    /// Catch up with the host's transport before processing a block, and
+    /// let it know about parameter changes it didn't make itself.
+    fn begin_block(&mut self) {
+        self.params.apply_staged();
+        if self.params.changed.swap(false, Ordering::AcqRel) {
+            self.host.update_display();
+        }
+        let info = self.host.get_time_info(TimeInfoFlags::TEMPO_VALID.bits());
+        let tempo = info
+            .filter(|info| info.flags & TimeInfoFlags::TEMPO_VALID.bits() != 0)
+            .map(|info| info.tempo)
+            .filter(|&tempo| tempo > 0.0);
+        self.engine.set_tempo(tempo);
+        // Hosts without time info never stop, as far as the engine knows.
+        if let Some(info) = info {
+            let playing = info.flags & TimeInfoFlags::TRANSPORT_PLAYING.bits() != 0;
+            self.engine.set_playing(playing);
+        }
+    }
+
+    /// Apply queued MIDI events which are due at `frame` of the current block,
+    /// preserving the order in which the host delivered them.
+    fn dispatch_events(&mut self, frame: usize) {
+        while let Some(ev) = self.pending_events.get(self.next_event).copied() {
+            if usize::try_from(ev.delta_frames).is_ok_and(|delta| delta > frame) {
+                break;
+            }
+            self.next_event += 1;
+            self.engine.process_midi(ev.data);
+            if self.params.is_thru_note(ev.data) {
+                self.thru_events.push(ev);
+            }
+        }
+    }
+
+    /// Index of the first input channel to capture from: the sidechain bus
+    /// following the main one when asked for and the host wired it, the main
+    /// bus otherwise.
+    fn sidechain_offset(&self, inputs: usize, channels: usize) -> usize {
+        let offset = self.engine.channels();
+        if self.params.switch(Parameter::Sidechain) && inputs >= offset + channels {
+            offset
         } else {
-            sample
+            0
+        }
+    }
+
+    /// Process a block of `samples` frames on a host bus of `(inputs,
+    /// outputs)` channels, reading samples with `input` and writing them
+    /// with `output`, both by channel and frame, once `begin_block` has
+    /// caught up with the host.
+    ///
+    /// Both precisions go through here, so they only differ in rounding to
+    /// and from `f32`.
+    fn process_block(
+        &mut self,
+        samples: usize,
+        (inputs, outputs): (usize, usize),
+        input: impl Fn(usize, usize) -> f64,
+        mut output: impl FnMut(usize, usize, f64),
+    ) {
+        if outputs > 0 {
+            self.bus_width = outputs;
+        }
+        let channels = outputs.min(self.engine.channels());
+        let sidechain = self.sidechain_offset(inputs, channels);
+        let sidechain_width = inputs - sidechain;
+        // The main bus is as wide as the engine, any sidechain following it.
+        let main_width = inputs.min(self.engine.channels());
+        let (mut scratch, mut source) = (take(&mut self.frame), take(&mut self.source));
+        let mut peak = 0.0f64;
+        for frame in 0..samples {
+            self.dispatch_events(frame);
+            let frame_samples = &mut scratch[..channels];
+            for (channel, sample) in frame_samples.iter_mut().enumerate() {
+                *sample = bus_sample(main_width, channels, channel, |bus_channel| {
+                    input(bus_channel, frame)
+                });
+            }
+            let source = &mut source[..channels];
+            for (channel, sample) in source.iter_mut().enumerate() {
+                *sample = bus_sample(sidechain_width, channels, channel, |bus_channel| {
+                    input(sidechain + bus_channel, frame)
+                });
+            }
+            self.engine.process_sidechain_frame(frame_samples, source);
+            for (channel, &sample) in frame_samples.iter().enumerate() {
+                output(channel, frame, sample);
+                peak = peak.max(sample.abs());
+            }
+            // Until `resume` widens the engine to a wider bus, outputs past
+            // the processed ones are silent rather than left with whatever
+            // the host put there.
+            for channel in channels..outputs {
+                output(channel, frame, 0.0);
+            }
+        }
+        (self.frame, self.source) = (scratch, source);
+        self.end_block(peak);
+    }
+
+    /// Report the block's `peak` to the meters and finish with its events.
+    fn end_block(&mut self, peak: f64) {
+        self.params.meters.update(peak, self.engine.is_frozen());
+        self.flush_events();
+    }
+
+    /// Apply events whose offset lies past the end of the block and start afresh.
+    fn flush_events(&mut self) {
+        self.dispatch_events(usize::MAX);
+        self.pending_events.clear();
+        self.next_event = 0;
+        if !self.thru_events.is_empty() {
+            self.send_buffer
+                .send_events(&self.thru_events, &mut self.host);
+            self.thru_events.clear();
         }
     }
 }
 
 impl Plugin for Zamerzika {
-    fn new(_host: HostCallback) -> Self {
-        let mut input: [RingBuffer; CHANNELS] = Default::default();
-        let mut output: [RingBuffer; CHANNELS] = Default::default();
-        for channel in 0..CHANNELS {
-            input[channel].resize(MAX_WINDOW_SIZE, 0.0);
-            output[channel].resize(MAX_WINDOW_SIZE, 0.0);
-        }
+    fn new(host: HostCallback) -> Self {
+        let params = Arc::new(ZamerzikaParameters::default());
         Zamerzika {
-            sample_rate: 48_000.0,
-            note: None,
-            input,
-            output,
-            window_size: 0,
-            xfade_countdown: Default::default(),
+            host,
+            engine: FreezeEngine::new(Arc::clone(&params), CHANNELS, FreezeConfig::default()),
+            params,
+            pending_events: Vec::with_capacity(MAX_PENDING_EVENTS),
+            next_event: 0,
+            thru_events: Vec::with_capacity(MAX_PENDING_EVENTS),
+            send_buffer: SendEventBuffer::new(MAX_PENDING_EVENTS),
+            frame: vec![0.0; CHANNELS],
+            source: vec![0.0; CHANNELS],
+            bus_width: CHANNELS,
         }
     }
 
     fn get_info(&self) -> Info {
         Info {
-            name: "Zamerzika".to_string(),
-            vendor: "Ruslan Prakapchuk".to_string(),
-            inputs: CHANNELS as _,
-            outputs: CHANNELS as _,
+            name: NAME.to_string(),
+            vendor: VENDOR.to_string(),
+            // The main bus followed by the sidechain.
+            inputs: (2 * self.engine.channels()) as _,
+            outputs: self.engine.channels() as _,
             midi_inputs: 1,
-            unique_id: 1_804_198_802,
-            version: 0001,
+            midi_outputs: 1,
+            parameters: Parameter::COUNT as _,
+            unique_id: UNIQUE_ID,
+            version: VERSION,
             category: Category::Effect,
+            initial_delay: LATENCY as _,
             f64_precision: true,
+            presets: PRESETS.len() as _,
+            preset_chunks: true,
             ..Default::default()
         }
     }
 
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
-            CanDo::ReceiveMidiEvent => Supported::Yes,
+            // MIDI comes in and, for MIDI thru, goes back out, and the tempo
+            // and transport are read from the host's time info.
+            CanDo::ReceiveEvents
+            | CanDo::ReceiveMidiEvent
+            | CanDo::SendEvents
+            | CanDo::SendMidiEvent
+            | CanDo::ReceiveTimeInfo => Supported::Yes,
+            // The host's soft bypass would go unheard, `vst` not passing it
+            // on: the bypass parameter fades instead. There are no MIDI
+            // programs either, only the factory presets.
             _ => Supported::No,
         }
     }
 
     fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = f64::from(rate);
+        self.engine.set_sample_rate(f64::from(rate));
+    }
+
+    fn suspend(&mut self) {
+        // Nothing sent out of a block which won't be finished.
+        self.thru_events.clear();
+    }
+
+    fn resume(&mut self) {
+        // Whatever was frozen or held before belongs to a stream which has
+        // ended, and its keys may have come up unheard.
+        self.params.apply_staged();
+        if self.bus_width != self.engine.channels() {
+            // Not processing, so the engine can be rebuilt to the width the
+            // host wired it up with, which comes up fresh anyway.
+            let config = FreezeConfig {
+                sample_rate: self.engine.sample_rate(),
+                ..FreezeConfig::default()
+            };
+            self.engine = FreezeEngine::new(Arc::clone(&self.params), self.bus_width, config);
+            self.frame = vec![0.0; self.bus_width];
+            self.source = vec![0.0; self.bus_width];
+        }
+        self.engine.reset();
+    }
+
+    fn get_tail_size(&self) -> isize {
+        // Zero would tell the host the tail is unknown, one that there's none.
+        self.engine.tail_frames().max(1) as _
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        // For each input and output channel.
-        for (channel, (input, output)) in buffer.zip().enumerate() {
-            // For each input sample and output sample in buffer.
-            for (in_sample, out_sample) in input.into_iter().zip(output.into_iter()) {
-                *out_sample = self.process_sample(channel, *in_sample as _) as _;
+        let samples = buffer.samples();
+        let (inputs, mut outputs) = buffer.split();
+        let (input_count, output_count) = (inputs.len(), outputs.len());
+        self.begin_block();
+        self.process_block(
+            samples,
+            (input_count, output_count),
+            |channel, frame| f64::from(inputs.get(channel)[frame]),
+            |channel, frame, sample| outputs.get_mut(channel)[frame] = sample as f32,
+        );
+    }
+
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let samples = buffer.samples();
+        let (inputs, mut outputs) = buffer.split();
+        let (input_count, output_count) = (inputs.len(), outputs.len());
+        self.begin_block();
+        self.process_block(
+            samples,
+            (input_count, output_count),
+            |channel, frame| inputs.get(channel)[frame],
+            |channel, frame, sample| outputs.get_mut(channel)[frame] = sample,
+        );
+    }
+
+    fn process_events(&mut self, events: &Events) {
+        // Hosts call this right before `process`, so events are only queued here
+        // and applied at their `delta_frames` offset within the next block.
+        for event in events.events() {
+            if let Event::Midi(ev) = event {
+                self.pending_events.push(ev);
             }
         }
     }
 
-    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
-        // For each input and output channel.
-        for (channel, (input, output)) in buffer.zip().enumerate() {
-            // For each input sample and output sample in buffer.
-            for (in_sample, out_sample) in input.into_iter().zip(output.into_iter()) {
-                *out_sample = self.process_sample(channel, *in_sample);
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(&self.params) as _
+    }
+}
+
+/// Host-automatable parameters, indexed the same way the host sees them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    /// Blend between live input (0) and frozen loop (1) while a note is held.
+    Mix,
+    /// Output level, see `gain_to_db` for the mapping.
+    Gain,
+    /// Play frozen loops backwards.
+    Reverse,
+    /// Length of the note-off fade.
+    Crossfade,
+    /// Shape of the note-off fade, see `CrossfadeCurve`.
+    Curve,
+    /// How much note-on velocity scales the frozen loop, 0 ignoring it.
+    Velocity,
+    /// MIDI channel to listen to, or all of them.
+    Channel,
+    /// Transposition of the frozen loops in whole octaves.
+    Octave,
+    /// Length of the loop seam smoothing applied at capture. Longer
+    /// smoothing hides the seam click better, but softens the loop's attack.
+    Smoothing,
+    /// Share of live input blended into held loops as they play, making
+    /// them evolve over time.
+    Feedback,
+    /// Reference frequency of A4 the loop lengths are derived from.
+    Tuning,
+    /// Loop or granular playback, see `FreezeMode`.
+    Mode,
+    /// Length of each grain in granular mode.
+    GrainSize,
+    /// Grains started per second in granular mode.
+    GrainDensity,
+    /// How far from the playhead grains are picked in granular mode.
+    GrainSpread,
+    /// Toggle freezes with note-ons, ignoring note-offs.
+    Latch,
+    /// Time for a new freeze to ramp in from the live input.
+    Attack,
+    /// Time for a released freeze to ramp out, unless the cross-fade is
+    /// longer.
+    Release,
+    /// High-pass the frozen signal to remove DC offset building up in the
+    /// loops, leaving the live one as it is.
+    DcBlock,
+    /// Capture the same stretch of every channel, or let each one start at
+    /// its own zero crossing for a wider image when `ZeroCross` is on.
+    StereoLink,
+    /// Number of pitch periods captured, longer loops suiting textured
+    /// rather than tonal input.
+    Periods,
+    /// Keep every new freeze playing after its note-off, stacking them
+    /// until cleared with `CC_CLEAR_LAYERS`.
+    Layer,
+    /// Retune the held freeze to overlapping notes instead of capturing
+    /// them, keeping the texture of the phrase's first note.
+    Legato,
+    /// Time legato retuning slides between pitches over.
+    Glide,
+    /// Treatment of the loop seam, over the smoothing length, see
+    /// `SeamMode`.
+    Seam,
+    /// Bit depth the frozen signal is quantized to, off at the top.
+    Bits,
+    /// Sample rate reduction of the frozen signal, off at the top.
+    Downsample,
+    /// Playback speed of the frozen loops, on top of octave and bend.
+    Speed,
+    /// Size the window to a division of the host tempo instead of the note.
+    Sync,
+    /// Note value the window lasts when synced.
+    Division,
+    /// Stereo position of the frozen signal.
+    Pan,
+    /// Stereo width of the frozen signal, from mono through as captured to
+    /// exaggerated.
+    Width,
+    /// Pass the input through untouched.
+    Bypass,
+    /// Freeze a single note at a time, chosen from the held keys.
+    Mono,
+    /// Which of the held keys sounds in mono mode.
+    Priority,
+    /// How much channel or poly aftertouch raises the frozen level.
+    Aftertouch,
+    /// What the modwheel sweeps.
+    ModTarget,
+    /// Kind of filter the frozen signal goes through, if any.
+    Filter,
+    /// Corner frequency of the filter.
+    Cutoff,
+    /// Emphasis of the filter around its cutoff.
+    Resonance,
+    /// Whether the output is soft-clipped, and whether the channels are
+    /// clipped together to keep the stereo balance.
+    Limiter,
+    /// Level the limiter never lets the output exceed.
+    Ceiling,
+    /// Freeze by itself while the input is loud, without any MIDI.
+    Trigger,
+    /// Input level starting an automatic freeze.
+    Threshold,
+    /// Input level below which the automatic freeze lets go.
+    ReleaseThreshold,
+    /// Key an automatic freeze captures its window for.
+    TriggerNote,
+    /// Capture from the sidechain inputs, letting the main ones play through.
+    Sidechain,
+    /// Semitones of pitch-bend at full wheel deflection.
+    BendRange,
+    /// Ignore note-offs, freezes lasting until all notes off or until this
+    /// is switched off.
+    Hold,
+    /// Nudge captured windows back to start at an upward zero crossing,
+    /// for a smaller jump at the seam.
+    ZeroCross,
+    /// Echo the notes played downstream, to drive another instrument in
+    /// sync with the freezes.
+    MidiThru,
+    /// Capture the latest stretch of input which wasn't silent, rather than
+    /// a gap the note-on happened to land in.
+    Snap,
+    /// Trim of the input, both captured and playing live.
+    InputGain,
+    /// Release every freeze when the host transport stops.
+    ReleaseOnStop,
+    /// Release a freeze on its own after this long, unless it's off.
+    MaxHold,
+    /// Length cap in beats, taking over from `MaxHold` while synced to a
+    /// host which reports its tempo.
+    MaxHoldBeats,
+    /// Delay of the right channel's loop behind the left, widening frozen
+    /// mono sources.
+    Haas,
+    /// Let the note-off velocity set the release cross-fade, between
+    /// `Crossfade` for the slowest releases and `MinCrossfade` for the
+    /// fastest.
+    ReleaseVelocity,
+    MinCrossfade,
+    /// Capture from the live input or, re-freezing, from what's frozen.
+    CaptureSource,
+    /// How far frozen loops are brought to the loudness of the live input.
+    LevelMatch,
+    /// Level of a copy of the loop an octave up, and how much of it feeds
+    /// back into the loop to climb further.
+    ShimmerLevel,
+    ShimmerFeedback,
+    /// Controller fading out and clearing every freeze and layer at once.
+    ResetController,
+    /// Oversampling of the loop reads, keeping loops played faster than
+    /// captured from aliasing, read cubically rather than linearly above
+    /// 1x.
+    Quality,
+    /// Capture the input summed to mono into every channel.
+    MonoInput,
+    /// Rate the loops start over at, gated by the depth for the second half
+    /// of every cycle. Zero turns the stutter off, synced or not.
+    StutterRate,
+    StutterDepth,
+    /// Octaves the live input's level opens the filter by, and how fast
+    /// that level is followed up and down.
+    EnvelopeDepth,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    /// Freeze the held keys as a single loop, captured when the first one
+    /// lands and held until the last one is up.
+    Chord,
+    /// Starting point of the randomness, the same seed playing the same
+    /// input back identically.
+    Seed,
+    /// How far into the same captured window the right channel's loop
+    /// starts, for a chorus-like thickening of mono-ish sources.
+    ChannelSpread,
+    /// Play frozen loops forwards and backwards in turn, over `Reverse`.
+    PingPong,
+    /// How long the live input plays through after a note-on before the
+    /// sound following its attack is frozen.
+    AttackHold,
+    /// Loop length the trigger mode freezes instead of the trigger note's
+    /// window, for use without any MIDI.
+    TriggerWindow,
+    /// Monitor the frozen signal alone, with no dry input, keeping the
+    /// loops going after their keys are up like `Hold`. Switched on with
+    /// nothing held, it brings back the loop frozen last.
+    SoloWet,
+    /// Lock the stutter to `StutterDivision` of the host tempo, when the
+    /// host reports one, rather than its rate.
+    StutterSync,
+    StutterDivision,
+}
+
+impl Parameter {
+    const ALL: [Parameter; 80] = [
+        Parameter::Mix,
+        Parameter::Gain,
+        Parameter::Reverse,
+        Parameter::Crossfade,
+        Parameter::Curve,
+        Parameter::Velocity,
+        Parameter::Channel,
+        Parameter::Octave,
+        Parameter::Smoothing,
+        Parameter::Feedback,
+        Parameter::Tuning,
+        Parameter::Mode,
+        Parameter::GrainSize,
+        Parameter::GrainDensity,
+        Parameter::GrainSpread,
+        Parameter::Latch,
+        Parameter::Attack,
+        Parameter::Release,
+        Parameter::DcBlock,
+        Parameter::StereoLink,
+        Parameter::Periods,
+        Parameter::Layer,
+        Parameter::Legato,
+        Parameter::Glide,
+        Parameter::Seam,
+        Parameter::Bits,
+        Parameter::Downsample,
+        Parameter::Speed,
+        Parameter::Sync,
+        Parameter::Division,
+        Parameter::Pan,
+        Parameter::Width,
+        Parameter::Bypass,
+        Parameter::Mono,
+        Parameter::Priority,
+        Parameter::Aftertouch,
+        Parameter::ModTarget,
+        Parameter::Filter,
+        Parameter::Cutoff,
+        Parameter::Resonance,
+        Parameter::Limiter,
+        Parameter::Ceiling,
+        Parameter::Trigger,
+        Parameter::Threshold,
+        Parameter::ReleaseThreshold,
+        Parameter::TriggerNote,
+        Parameter::Sidechain,
+        Parameter::BendRange,
+        Parameter::Hold,
+        Parameter::ZeroCross,
+        Parameter::MidiThru,
+        Parameter::Snap,
+        Parameter::InputGain,
+        Parameter::ReleaseOnStop,
+        Parameter::MaxHold,
+        Parameter::MaxHoldBeats,
+        Parameter::Haas,
+        Parameter::ReleaseVelocity,
+        Parameter::MinCrossfade,
+        Parameter::CaptureSource,
+        Parameter::LevelMatch,
+        Parameter::ShimmerLevel,
+        Parameter::ShimmerFeedback,
+        Parameter::ResetController,
+        Parameter::Quality,
+        Parameter::MonoInput,
+        Parameter::StutterRate,
+        Parameter::StutterDepth,
+        Parameter::EnvelopeDepth,
+        Parameter::EnvelopeAttack,
+        Parameter::EnvelopeRelease,
+        Parameter::Chord,
+        Parameter::Seed,
+        Parameter::ChannelSpread,
+        Parameter::PingPong,
+        Parameter::AttackHold,
+        Parameter::TriggerWindow,
+        Parameter::SoloWet,
+        Parameter::StutterSync,
+        Parameter::StutterDivision,
+    ];
+    const COUNT: usize = Self::ALL.len();
+
+    fn from_index(index: i32) -> Option<Parameter> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| Self::ALL.get(index).copied())
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Parameter::Mix => "Mix",
+            Parameter::Gain => "Gain",
+            Parameter::Reverse => "Reverse",
+            Parameter::Crossfade => "Crossfade",
+            Parameter::Curve => "Curve",
+            Parameter::Velocity => "Velocity",
+            Parameter::Channel => "Channel",
+            Parameter::Octave => "Octave",
+            Parameter::Smoothing => "Smoothing",
+            Parameter::Feedback => "Feedback",
+            Parameter::Tuning => "Tuning",
+            Parameter::Mode => "Mode",
+            Parameter::GrainSize => "Grain size",
+            Parameter::GrainDensity => "Grain density",
+            Parameter::GrainSpread => "Grain spread",
+            Parameter::Latch => "Latch",
+            Parameter::Attack => "Attack",
+            Parameter::Release => "Release",
+            Parameter::DcBlock => "DC block",
+            Parameter::StereoLink => "Stereo link",
+            Parameter::Periods => "Periods",
+            Parameter::Layer => "Layer",
+            Parameter::Legato => "Legato",
+            Parameter::Glide => "Glide",
+            Parameter::Seam => "Seam",
+            Parameter::Bits => "Bits",
+            Parameter::Downsample => "Downsample",
+            Parameter::Speed => "Speed",
+            Parameter::Sync => "Sync",
+            Parameter::Division => "Division",
+            Parameter::Pan => "Pan",
+            Parameter::Width => "Width",
+            Parameter::Bypass => "Bypass",
+            Parameter::Mono => "Mono",
+            Parameter::Priority => "Priority",
+            Parameter::Aftertouch => "Aftertouch",
+            Parameter::ModTarget => "Mod Target",
+            Parameter::Filter => "Filter",
+            Parameter::Cutoff => "Cutoff",
+            Parameter::Resonance => "Resonance",
+            Parameter::Limiter => "Limiter",
+            Parameter::Ceiling => "Ceiling",
+            Parameter::Trigger => "Trigger",
+            Parameter::Threshold => "Threshold",
+            Parameter::ReleaseThreshold => "Release Threshold",
+            Parameter::TriggerNote => "Trigger Note",
+            Parameter::Sidechain => "Sidechain",
+            Parameter::BendRange => "Bend Range",
+            Parameter::Hold => "Hold",
+            Parameter::ZeroCross => "Zero Cross",
+            Parameter::MidiThru => "MIDI Thru",
+            Parameter::Snap => "Snap",
+            Parameter::InputGain => "Input Gain",
+            Parameter::ReleaseOnStop => "Release On Stop",
+            Parameter::MaxHold => "Max Hold",
+            Parameter::MaxHoldBeats => "Max Hold Beats",
+            Parameter::Haas => "Haas",
+            Parameter::ReleaseVelocity => "Release Velocity",
+            Parameter::MinCrossfade => "Min Crossfade",
+            Parameter::CaptureSource => "Capture Source",
+            Parameter::LevelMatch => "Level Match",
+            Parameter::ShimmerLevel => "Shimmer Level",
+            Parameter::ShimmerFeedback => "Shimmer Feedback",
+            Parameter::ResetController => "Reset CC",
+            Parameter::Quality => "Quality",
+            Parameter::MonoInput => "Mono Input",
+            Parameter::StutterRate => "Stutter Rate",
+            Parameter::StutterDepth => "Stutter Depth",
+            Parameter::EnvelopeDepth => "Envelope Depth",
+            Parameter::EnvelopeAttack => "Envelope Attack",
+            Parameter::EnvelopeRelease => "Envelope Release",
+            Parameter::Chord => "Chord",
+            Parameter::Seed => "Seed",
+            Parameter::ChannelSpread => "Channel Spread",
+            Parameter::PingPong => "Ping-Pong",
+            Parameter::AttackHold => "Attack Hold",
+            Parameter::TriggerWindow => "Trigger Window",
+            Parameter::SoloWet => "Solo Wet",
+            Parameter::StutterSync => "Stutter Sync",
+            Parameter::StutterDivision => "Stutter Division",
+        }
+    }
+
+    fn default_value(self) -> f32 {
+        match self {
+            Parameter::Mix => 1.0,
+            Parameter::Gain => GAIN_UNITY,
+            Parameter::Reverse => 0.0,
+            Parameter::Crossfade => XFADE_FRAMES as f32 / 48.0 / MAX_XFADE_MS,
+            Parameter::Curve => 0.0,
+            Parameter::Velocity => 1.0,
+            Parameter::Channel => 0.0,
+            Parameter::Octave => 0.5,
+            Parameter::Smoothing => XFADE_FRAMES as f32 / 48.0 / MAX_SMOOTH_MS,
+            Parameter::Feedback => 0.0,
+            Parameter::Tuning => 0.5,
+            Parameter::Mode => 0.0,
+            Parameter::GrainSize => 0.5,
+            Parameter::GrainDensity => 0.5,
+            Parameter::GrainSpread => 0.0,
+            Parameter::Latch => 0.0,
+            Parameter::Attack => 0.0,
+            Parameter::Release => 0.0,
+            Parameter::DcBlock => 1.0,
+            Parameter::StereoLink => 1.0,
+            Parameter::Periods => 0.0,
+            Parameter::Layer => 0.0,
+            Parameter::Legato => 0.0,
+            Parameter::Glide => 0.0,
+            Parameter::Seam => 0.0,
+            Parameter::Bits => 1.0,
+            Parameter::Downsample => 1.0,
+            Parameter::Speed => 0.5,
+            Parameter::Sync => 0.0,
+            Parameter::Division => 0.5,
+            Parameter::Pan => 0.5,
+            Parameter::Width => 0.5,
+            Parameter::Bypass => 0.0,
+            Parameter::Mono => 0.0,
+            Parameter::Priority => 0.0,
+            Parameter::Aftertouch => 0.0,
+            Parameter::ModTarget => 0.0,
+            Parameter::Filter => 0.0,
+            Parameter::Cutoff => 1.0,
+            Parameter::Resonance => 0.0,
+            Parameter::Limiter => 0.0,
+            Parameter::Ceiling => 1.0,
+            Parameter::Trigger => 0.0,
+            // -20 and -30 dB.
+            Parameter::Threshold => 2.0 / 3.0,
+            Parameter::ReleaseThreshold => 0.5,
+            // Middle C.
+            Parameter::TriggerNote => 60.0 / 127.0,
+            Parameter::Sidechain => 0.0,
+            // The General MIDI default of 2 semitones.
+            Parameter::BendRange => 2.0 / MAX_BEND_RANGE as f32,
+            Parameter::Hold => 0.0,
+            Parameter::ZeroCross => 0.0,
+            Parameter::MidiThru => 0.0,
+            Parameter::Snap => 0.0,
+            Parameter::InputGain => 0.5,
+            Parameter::ReleaseOnStop => 0.0,
+            Parameter::MaxHold => 0.0,
+            Parameter::MaxHoldBeats => 0.0,
+            Parameter::Haas => 0.0,
+            Parameter::ReleaseVelocity => 0.0,
+            Parameter::MinCrossfade => 0.0,
+            Parameter::CaptureSource => 0.0,
+            Parameter::LevelMatch => 0.0,
+            Parameter::ShimmerLevel => 0.0,
+            Parameter::ShimmerFeedback => 0.0,
+            // The last of the controllers, CC 119.
+            Parameter::ResetController => 1.0,
+            Parameter::Quality => 0.0,
+            Parameter::MonoInput => 0.0,
+            Parameter::StutterRate => 0.0,
+            Parameter::StutterDepth => 1.0,
+            Parameter::EnvelopeDepth => 0.0,
+            // 20 and 245 ms.
+            Parameter::EnvelopeAttack => 0.1,
+            Parameter::EnvelopeRelease => 0.35,
+            Parameter::Chord => 0.0,
+            Parameter::Seed => 0.0,
+            Parameter::ChannelSpread => 0.0,
+            Parameter::PingPong => 0.0,
+            Parameter::AttackHold => 0.0,
+            Parameter::TriggerWindow => 0.0,
+            Parameter::SoloWet => 0.0,
+            Parameter::StutterSync => 0.0,
+            // 1/16.
+            Parameter::StutterDivision => 0.75,
+        }
+    }
+
+    /// Human-readable rendering of the normalized `value`.
+    fn text(self, value: f32) -> String {
+        match self {
+            Parameter::Mix => format!("{:.0}", value * 100.0),
+            Parameter::Gain => format!("{:.1}", gain_to_db(value)),
+            Parameter::Reverse => switch_text(value).to_string(),
+            Parameter::Crossfade | Parameter::MinCrossfade => {
+                format!("{:.2}", value * MAX_XFADE_MS)
+            }
+            Parameter::Curve => ["Linear", "Equal power"][choice(value, 2)].to_string(),
+            Parameter::Velocity | Parameter::Aftertouch => format!("{:.0}", value * 100.0),
+            Parameter::Channel => match channel_filter(value) {
+                Some(channel) => (channel + 1).to_string(),
+                None => "Omni".to_string(),
+            },
+            Parameter::Octave => format!("{:+}", octave(value)),
+            Parameter::Periods => periods(value).to_string(),
+            Parameter::Smoothing => format!("{:.2}", value * MAX_SMOOTH_MS),
+            Parameter::Haas => match value * MAX_HAAS_MS {
+                ms if ms > 0.0 => format!("{:.1}", ms),
+                _ => "Off".to_string(),
+            },
+            Parameter::ChannelSpread => match value * MAX_CHANNEL_SPREAD_MS {
+                ms if ms > 0.0 => format!("{:.2}", ms),
+                _ => "Off".to_string(),
+            },
+            Parameter::Feedback => format!("{:.0}", value * 100.0),
+            Parameter::Tuning => format!("{:.1}", tuning_hz(value)),
+            Parameter::Mode => ["Loop", "Granular"][choice(value, 2)].to_string(),
+            Parameter::Quality => ["1x", "2x", "4x"][choice(value, 3)].to_string(),
+            Parameter::Seam => ["Average", "Overlap-add"][choice(value, 2)].to_string(),
+            Parameter::CaptureSource => ["Input", "Frozen output"][choice(value, 2)].to_string(),
+            Parameter::Bits => match bits(value) {
+                Some(bits) => format!("{:.1}", bits),
+                None => "Off".to_string(),
+            },
+            Parameter::Speed => format!("{:.2}x", speed(value)),
+            Parameter::Division => format!("1/{}", division(value)),
+            Parameter::StutterDivision => stutter_division(value).0.to_string(),
+            Parameter::Pan => match pan(value) {
+                pan if pan.abs() < 0.005 => "C".to_string(),
+                pan if pan < 0.0 => format!("L {:.0}", -100.0 * pan),
+                pan => format!("R {:.0}", 100.0 * pan),
+            },
+            Parameter::Width => format!("{:.0}", 100.0 * width(value)),
+            Parameter::Downsample => match downsample(value) {
+                1 => "Off".to_string(),
+                hold => format!("1/{}", hold),
+            },
+            Parameter::GrainSize => format!("{:.1}", grain_ms(value)),
+            Parameter::GrainDensity => format!("{:.1}", grain_density(value)),
+            Parameter::StutterRate => match stutter_rate(value) {
+                Some(hz) => format!("{:.2}", hz),
+                None => "Off".to_string(),
+            },
+            Parameter::GrainSpread
+            | Parameter::StutterDepth
+            | Parameter::LevelMatch
+            | Parameter::ShimmerLevel
+            | Parameter::ShimmerFeedback => format!("{:.0}", value * 100.0),
+            Parameter::EnvelopeDepth => format!("{:.1}", envelope_octaves(value)),
+            Parameter::Latch
+            | Parameter::DcBlock
+            | Parameter::StereoLink
+            | Parameter::Layer
+            | Parameter::Legato
+            | Parameter::Sync
+            | Parameter::Bypass
+            | Parameter::Mono
+            | Parameter::Trigger
+            | Parameter::Sidechain
+            | Parameter::Hold
+            | Parameter::ZeroCross
+            | Parameter::MidiThru
+            | Parameter::Snap
+            | Parameter::ReleaseOnStop
+            | Parameter::ReleaseVelocity
+            | Parameter::MonoInput
+            | Parameter::Chord
+            | Parameter::PingPong
+            | Parameter::SoloWet
+            | Parameter::StutterSync => switch_text(value).to_string(),
+            Parameter::Priority => ["Last", "Highest", "Lowest"][choice(value, 3)].to_string(),
+            Parameter::ModTarget => ["Speed", "Mix", "Cutoff"][choice(value, 3)].to_string(),
+            Parameter::Filter => ["Off", "Low-pass", "High-pass"][choice(value, 3)].to_string(),
+            Parameter::Cutoff => match cutoff(value) {
+                hz if hz < 1000.0 => format!("{:.0}", hz),
+                hz => format!("{:.2}", hz / 1000.0),
+            },
+            Parameter::Resonance => format!("Q {:.2}", resonance(value)),
+            Parameter::Limiter => ["Off", "Linked", "Per channel"][choice(value, 3)].to_string(),
+            Parameter::Ceiling => format!("{:.1}", ceiling_db(value)),
+            // Applies to the live signal as well, but not to the bypassed
+            // one, which passes through untouched.
+            Parameter::InputGain => format!("{:+.1}", input_gain_db(value)),
+            Parameter::AttackHold => match attack_hold_ms(value) {
+                ms if ms > 0.0 => format!("{:.1}", ms),
+                _ => "Off".to_string(),
+            },
+            Parameter::TriggerWindow => match trigger_window_ms(value) {
+                Some(ms) => format!("{:.1}", ms),
+                None => "Note".to_string(),
+            },
+            Parameter::MaxHold => match hold_seconds(value) {
+                Some(seconds) => format!("{:.1}", seconds),
+                None => "Off".to_string(),
+            },
+            Parameter::MaxHoldBeats => match hold_beats(value) {
+                Some(beats) => beats.to_string(),
+                None => "Off".to_string(),
+            },
+            Parameter::Threshold | Parameter::ReleaseThreshold => {
+                format!("{:.1}", threshold_db(value))
+            }
+            Parameter::TriggerNote => note_name(trigger_note(value)),
+            Parameter::ResetController => match reset_controller(value) {
+                Some(controller) => controller.to_string(),
+                None => "Off".to_string(),
+            },
+            Parameter::BendRange => format!("±{}", bend_range(value)),
+            Parameter::Seed => seed(value).to_string(),
+            Parameter::Attack
+            | Parameter::Release
+            | Parameter::Glide
+            | Parameter::EnvelopeAttack
+            | Parameter::EnvelopeRelease => {
+                format!("{:.1}", envelope_ms(value))
             }
         }
     }
 
-    fn process_events(&mut self, events: &Events) {
-        for event in events.events() {
-            match event {
-                Event::Midi(ev) => match ev.data[0] {
-                    0x80 => {
-                        if let Some(note) = self.note {
-                            if note == ev.data[1] {
-                                self.note = None;
-                                for channel in 0..CHANNELS {
-                                    self.xfade_countdown[channel] = XFADE_FRAMES;
-                                }
-                            }
-                        }
-                    }
-                    // TODO Moar time precision, freeze with `ev.delta_frame` delay.
-                    // TODO Polyphony?
-                    0x90 => {
-                        let pitch = ev.data[1];
-                        self.note = Some(pitch);
-                        self.window_size =
-                            (self.sample_rate / midi_pitch_to_freq(pitch)).round() as _;
-                        for channel in 0..CHANNELS {
-                            self.input[channel].open_window(self.window_size);
-                            self.output[channel].resize(self.window_size, 0.0);
-                            for _ in 0..self.window_size {
-                                self.output[channel].write(self.input[channel].read());
-                            }
-                            self.output[channel].smooth(XFADE_FRAMES);
-                        }
-                    }
-                    _ => (),
-                },
-                _ => (),
+    /// Unit of `text` at `value`, empty for values without one, like "Off".
+    fn label(self, value: f32) -> &'static str {
+        match self {
+            Parameter::Mix
+            | Parameter::Velocity
+            | Parameter::Aftertouch
+            | Parameter::Feedback
+            | Parameter::Width
+            | Parameter::GrainSpread
+            | Parameter::StutterDepth
+            | Parameter::LevelMatch
+            | Parameter::ShimmerLevel
+            | Parameter::ShimmerFeedback => "%",
+            Parameter::StutterRate if stutter_rate(value).is_some() => "Hz",
+            Parameter::Gain
+            | Parameter::Ceiling
+            | Parameter::InputGain
+            | Parameter::Threshold
+            | Parameter::ReleaseThreshold => "dB",
+            Parameter::Crossfade
+            | Parameter::MinCrossfade
+            | Parameter::Smoothing
+            | Parameter::GrainSize
+            | Parameter::Attack
+            | Parameter::Release
+            | Parameter::Glide
+            | Parameter::EnvelopeAttack
+            | Parameter::EnvelopeRelease => "ms",
+            Parameter::EnvelopeDepth => "oct",
+            Parameter::Haas | Parameter::ChannelSpread | Parameter::AttackHold if value > 0.0 => {
+                "ms"
+            }
+            Parameter::Tuning | Parameter::GrainDensity => "Hz",
+            Parameter::Cutoff if cutoff(value) < 1000.0 => "Hz",
+            Parameter::Cutoff => "kHz",
+            Parameter::MaxHold if hold_seconds(value).is_some() => "s",
+            Parameter::TriggerWindow if value > 0.0 => "ms",
+            Parameter::MaxHoldBeats => match hold_beats(value) {
+                Some(1) => "beat",
+                Some(_) => "beats",
+                None => "",
+            },
+            Parameter::BendRange => "st",
+            _ => "",
+        }
+    }
+
+    /// `text` followed by its `label`, for hosts which show them together.
+    fn display(self, value: f32) -> String {
+        match self.label(value) {
+            "" => self.text(value),
+            label => format!("{} {}", self.text(value), label),
+        }
+    }
+
+    /// Number of values the parameter steps through, evenly spread over the
+    /// normalized range, or `None` if it's continuous.
+    fn steps(self) -> Option<usize> {
+        match self {
+            Parameter::Reverse
+            | Parameter::Latch
+            | Parameter::DcBlock
+            | Parameter::StereoLink
+            | Parameter::Layer
+            | Parameter::Legato
+            | Parameter::Sync
+            | Parameter::Bypass
+            | Parameter::Mono
+            | Parameter::Trigger
+            | Parameter::Sidechain
+            | Parameter::Hold
+            | Parameter::ZeroCross
+            | Parameter::MidiThru
+            | Parameter::Snap
+            | Parameter::ReleaseOnStop
+            | Parameter::ReleaseVelocity
+            | Parameter::MonoInput
+            | Parameter::Chord
+            | Parameter::PingPong
+            | Parameter::SoloWet
+            | Parameter::StutterSync
+            | Parameter::Curve
+            | Parameter::Mode
+            | Parameter::Seam
+            | Parameter::CaptureSource => Some(2),
+            Parameter::Quality
+            | Parameter::Priority
+            | Parameter::ModTarget
+            | Parameter::Filter
+            | Parameter::Limiter => Some(3),
+            Parameter::Channel => Some(17),
+            Parameter::Octave => Some(2 * MAX_OCTAVE_SHIFT as usize + 1),
+            Parameter::Periods => Some(4),
+            Parameter::Division => Some(5),
+            Parameter::StutterDivision => Some(STUTTER_DIVISIONS.len()),
+            Parameter::Downsample => Some(MAX_DOWNSAMPLE),
+            Parameter::MaxHoldBeats => Some(8),
+            Parameter::TriggerNote => Some(128),
+            Parameter::ResetController => Some(121),
+            Parameter::BendRange => Some(MAX_BEND_RANGE + 1),
+            Parameter::Seed => Some(SEEDS),
+            _ => None,
+        }
+    }
+
+    /// Normalized value shown as `text`, with or without its label, by
+    /// searching what `text` shows rather than inverting every mapping.
+    /// Numbers between the shown ones pick the nearest.
+    fn parse(self, text: &str) -> Option<f32> {
+        // Finer than any continuous parameter shows.
+        const RESOLUTION: usize = 10_000;
+        let text = text.trim();
+        let count = self.steps().unwrap_or(RESOLUTION + 1);
+        let values = (0..count).map(|step| step as f32 / (count - 1) as f32);
+        let shown = values.clone().find(|&value| {
+            self.text(value).eq_ignore_ascii_case(text)
+                || self.display(value).eq_ignore_ascii_case(text)
+        });
+        if shown.is_some() {
+            return shown;
+        }
+        let number = shown_number(text)?;
+        values
+            .filter_map(|value| {
+                let distance = (shown_number(&self.display(value))? - number).abs();
+                Some((value, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(value, _)| value)
+    }
+}
+
+/// Number shown in a parameter's `display`, with kilo prefixes multiplied
+/// out: "L 50" is -50, "C" 0, "1/16" 16 and "1.50 kHz" 1500.
+fn shown_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (sign, text) = match text.as_bytes().first()? {
+        b'C' | b'c' if text.len() == 1 => return Some(0.0),
+        b'L' | b'l' => (-1.0, &text[1..]),
+        b'R' | b'r' => (1.0, &text[1..]),
+        _ => (1.0, text),
+    };
+    let text = text
+        .trim_start()
+        .trim_start_matches(['Q', 'q', '±'])
+        .trim_start_matches("1/")
+        .trim_start();
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+        .unwrap_or(text.len());
+    let number: f64 = text[..end].parse().ok()?;
+    let scale = match text[end..].trim_start().as_bytes().first() {
+        Some(b'k' | b'K') => 1000.0,
+        _ => 1.0,
+    };
+    Some(sign * scale * number)
+}
+
+/// Sample of `channel` of a frame processed `channels` wide, from an input
+/// bus `inputs` wide read with `sample`: a mono input plays on every
+/// channel, and a wider one is averaged down for a mono output.
+fn bus_sample(
+    inputs: usize,
+    channels: usize,
+    channel: usize,
+    sample: impl Fn(usize) -> f64,
+) -> f64 {
+    match inputs {
+        0 => 0.0,
+        inputs if channels == 1 => (0..inputs).map(sample).sum::<f64>() / inputs as f64,
+        inputs => sample(channel.min(inputs - 1)),
+    }
+}
+
+/// On/off parameters are stored normalized and flip at the midpoint.
+fn switch_text(value: f32) -> &'static str {
+    if value >= 0.5 {
+        "On"
+    } else {
+        "Off"
+    }
+}
+
+/// Index of the option selected by `value` out of `options` evenly spread
+/// over the normalized range.
+fn choice(value: f32, options: usize) -> usize {
+    ((value * (options - 1) as f32).round() as usize).min(options - 1)
+}
+
+/// Seed options, numbered from 0.
+fn seed(value: f32) -> u32 {
+    choice(value, SEEDS) as u32
+}
+
+/// Reset controller options are off followed by the controllers from 0 to
+/// 119, those above being channel mode messages.
+fn reset_controller(value: f32) -> Option<u8> {
+    match choice(value, 121) {
+        0 => None,
+        option => Some(option as u8 - 1),
+    }
+}
+
+/// Channel parameter options are omni followed by the 16 MIDI channels.
+fn channel_filter(value: f32) -> Option<u8> {
+    match choice(value, 17) {
+        0 => None,
+        option => Some(option as u8 - 1),
+    }
+}
+
+/// Octave shift from -`MAX_OCTAVE_SHIFT` to +`MAX_OCTAVE_SHIFT`.
+fn octave(value: f32) -> i32 {
+    let options = 2 * MAX_OCTAVE_SHIFT as usize + 1;
+    choice(value, options) as i32 - MAX_OCTAVE_SHIFT
+}
+
+/// Bit depth from 1 to just below `MAX_BITS`, reaching which bypasses
+/// quantization.
+fn bits(value: f32) -> Option<f64> {
+    let bits = 1.0 + f64::from(value) * (MAX_BITS - 1.0);
+    (bits < MAX_BITS).then_some(bits)
+}
+
+/// Factor the sample rate is divided by, 1 at the top of the range.
+fn downsample(value: f32) -> usize {
+    1 + ((1.0 - value) * (MAX_DOWNSAMPLE - 1) as f32).round() as usize
+}
+
+/// Pan position from -1 (left) to 1 (right).
+fn pan(value: f32) -> f64 {
+    2.0 * f64::from(value) - 1.0
+}
+
+/// Stereo width from 0 (mono) to 2, 1 in the centre.
+fn width(value: f32) -> f64 {
+    2.0 * f64::from(value)
+}
+
+/// Division parameter options are the note values from 1/4 to 1/64, as
+/// longer ones hardly ever fit in a window.
+fn division(value: f32) -> usize {
+    4 << choice(value, 5)
+}
+
+/// Speed factor from a quarter to four times, exactly 1 in the centre.
+fn speed(value: f32) -> f64 {
+    4f64.powf(2.0 * f64::from(value) - 1.0)
+}
+
+/// Periods parameter options are the powers of two from 1 to 8.
+fn periods(value: f32) -> usize {
+    1 << choice(value, 4)
+}
+
+/// A4 reference frequency, covering Baroque 415 Hz up to modern 442+ Hz
+/// and centred at 440 Hz.
+fn tuning_hz(value: f32) -> f64 {
+    400.0 + 80.0 * f64::from(value)
+}
+
+/// Envelope segment length in milliseconds, squared for finer control of
+/// short times.
+fn envelope_ms(value: f32) -> f32 {
+    value * value * MAX_ENVELOPE_MS
+}
+
+/// Octaves a full scale input opens the filter by, from none up to
+/// `MAX_ENVELOPE_OCTAVES`.
+fn envelope_octaves(value: f32) -> f64 {
+    f64::from(value) * MAX_ENVELOPE_OCTAVES
+}
+
+/// Attack hold in milliseconds, squared like the envelope times.
+fn attack_hold_ms(value: f32) -> f32 {
+    value * value * MAX_ATTACK_HOLD_MS
+}
+
+/// Window length of the trigger mode in milliseconds, squared like the
+/// envelope times, or `None` at zero for the trigger note's.
+fn trigger_window_ms(value: f32) -> Option<f32> {
+    (value > 0.0).then_some(value * value * MAX_TRIGGER_WINDOW_MS)
+}
+
+/// Freeze length cap in seconds, squared like the envelope times, or
+/// `None` at zero for no cap.
+fn hold_seconds(value: f32) -> Option<f32> {
+    (value > 0.0).then_some(value * value * MAX_HOLD_SECONDS)
+}
+
+/// Freeze length cap options in beats are off and the powers of two from 1
+/// to 64.
+fn hold_beats(value: f32) -> Option<usize> {
+    match choice(value, 8) {
+        0 => None,
+        option => Some(1 << (option - 1)),
+    }
+}
+
+/// Stutter cycles per second, from 0.5 to 32 on a logarithmic scale, or
+/// `None` at zero for no stutter.
+fn stutter_rate(value: f32) -> Option<f64> {
+    (value > 0.0).then(|| 0.5 * 64f64.powf(f64::from(value)))
+}
+
+/// Stutter division options, straight and triplet, with their lengths in
+/// beats.
+const STUTTER_DIVISIONS: [(&str, f64); 9] = [
+    ("1/1", 4.0),
+    ("1/2", 2.0),
+    ("1/4", 1.0),
+    ("1/4T", 2.0 / 3.0),
+    ("1/8", 0.5),
+    ("1/8T", 1.0 / 3.0),
+    ("1/16", 0.25),
+    ("1/16T", 1.0 / 6.0),
+    ("1/32", 0.125),
+];
+
+fn stutter_division(value: f32) -> (&'static str, f64) {
+    STUTTER_DIVISIONS[choice(value, STUTTER_DIVISIONS.len())]
+}
+
+/// Grain length in milliseconds, from 10 to 500 on a logarithmic scale.
+fn grain_ms(value: f32) -> f64 {
+    10.0 * 50f64.powf(f64::from(value))
+}
+
+/// Grains per second, from 2 to 200 on a logarithmic scale.
+fn grain_density(value: f32) -> f64 {
+    2.0 * 100f64.powf(f64::from(value))
+}
+
+/// Filter cutoff in Hz, from 20 to 20000 on a logarithmic scale.
+fn cutoff(value: f32) -> f64 {
+    20.0 * 1000f64.powf(f64::from(value))
+}
+
+/// Filter quality factor, from a flat 0.71 up to a ringing 22.6.
+fn resonance(value: f32) -> f64 {
+    FRAC_1_SQRT_2 * 32f64.powf(f64::from(value))
+}
+
+/// Normalized gain value corresponding to exactly 0 dB.
+const GAIN_UNITY: f32 = 0.75;
+const GAIN_MIN_DB: f64 = -60.0;
+const GAIN_MAX_DB: f64 = 12.0;
+
+/// Map normalized gain to decibels: the range below `GAIN_UNITY` covers
+/// attenuation and the rest boost, so unity never suffers rounding.
+fn gain_to_db(value: f32) -> f64 {
+    let value = f64::from(value);
+    let unity = f64::from(GAIN_UNITY);
+    if value < unity {
+        GAIN_MIN_DB * (1.0 - value / unity)
+    } else {
+        GAIN_MAX_DB * (value - unity) / (1.0 - unity)
+    }
+}
+
+/// Lowest trigger threshold, the top of the range being full scale.
+const THRESHOLD_MIN_DB: f64 = -60.0;
+
+fn threshold_db(value: f32) -> f64 {
+    THRESHOLD_MIN_DB * (1.0 - f64::from(value))
+}
+
+/// Bend range parameter options are the whole semitones up to
+/// `MAX_BEND_RANGE`.
+fn bend_range(value: f32) -> usize {
+    choice(value, MAX_BEND_RANGE + 1)
+}
+
+/// Trigger note parameter options are every MIDI key.
+fn trigger_note(value: f32) -> u8 {
+    choice(value, 128) as u8
+}
+
+/// Name of a MIDI key, with middle C being C4.
+fn note_name(pitch: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    let octave = i32::from(pitch / 12) - 1;
+    format!("{}{}", NAMES[usize::from(pitch % 12)], octave)
+}
+
+/// Input trim range either way from unity.
+const INPUT_GAIN_RANGE_DB: f64 = 24.0;
+
+fn input_gain_db(value: f32) -> f64 {
+    INPUT_GAIN_RANGE_DB * (2.0 * f64::from(value) - 1.0)
+}
+
+/// Lowest limiter ceiling, the top of the range being full scale.
+const CEILING_MIN_DB: f64 = -24.0;
+
+fn ceiling_db(value: f32) -> f64 {
+    CEILING_MIN_DB * (1.0 - f64::from(value))
+}
+
+fn db_to_amplitude(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Output level and freeze state as of the latest block, for a GUI to poll
+/// from its own thread.
+pub struct Meters {
+    peak: AtomicFloat,
+    frozen: AtomicBool,
+}
+
+impl Meters {
+    /// Highest absolute sample of the latest block, across channels.
+    pub fn peak(&self) -> f32 {
+        self.peak.get()
+    }
+
+    /// Whether anything frozen was playing by the end of the latest block.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Relaxed)
+    }
+
+    fn update(&self, peak: f64, frozen: bool) {
+        self.peak.set(peak as f32);
+        self.frozen.store(frozen, Ordering::Relaxed);
+    }
+}
+
+impl Default for Meters {
+    fn default() -> Self {
+        Meters {
+            peak: AtomicFloat::new(0.0),
+            frozen: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Normalized parameter values, shared between the host and the audio thread.
+pub struct ZamerzikaParameters {
+    values: [AtomicFloat; Parameter::COUNT],
+    /// Whether `tuning_table` overrides equal temperament.
+    tuning_table_enabled: AtomicBool,
+    /// Bits of the per-note `f64` frequencies of the loaded tuning table.
+    tuning_table: [AtomicU64; tuning::NOTES],
+    /// Values changed other than through the host, which should refresh
+    /// its display of them.
+    changed: AtomicBool,
+    /// Index into `PRESETS` of the preset last changed to.
+    preset: AtomicUsize,
+    /// Values of a preset or chunk loaded since the audio thread last
+    /// caught up, applied all at once by `apply_staged` so no block runs
+    /// with half of them.
+    staged: Mutex<Option<[f32; Parameter::COUNT]>>,
+    /// Parameters set while something else held `staged`, whose values
+    /// applying it has to keep.
+    overridden: [AtomicBool; Parameter::COUNT],
+    meters: Meters,
+}
+
+impl ZamerzikaParameters {
+    pub fn meters(&self) -> &Meters {
+        &self.meters
+    }
+
+    /// Normalized value of `parameter`, from 0 to 1, as processing sees it.
+    /// A preset loaded since the last block doesn't show here until the
+    /// next one starts, see `value`.
+    pub fn get(&self, parameter: Parameter) -> f32 {
+        self.values[parameter as usize].get()
+    }
+
+    /// Set `parameter` to the normalized `value` the way a host would,
+    /// clamped to 0 to 1. Never waits, hosts set parameters from the audio
+    /// thread too.
+    pub fn set(&self, parameter: Parameter, value: f32) {
+        let value = value.clamp(0.0, 1.0);
+        // Set after a preset load, so it has to outlast applying that.
+        let staged = match self.staged.try_lock() {
+            Ok(staged) => Some(staged),
+            Err(TryLockError::Poisoned(staged)) => Some(staged.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        };
+        match staged {
+            Some(mut staged) => {
+                if let Some(staged) = staged.as_mut() {
+                    staged[parameter as usize] = value;
+                }
+            }
+            None => self.overridden[parameter as usize].store(true, Ordering::Release),
+        }
+        self.values[parameter as usize].set(value);
+    }
+
+    /// Normalized value of `parameter` as the host last set or loaded it,
+    /// which is what it should be shown and saved as.
+    pub fn value(&self, parameter: Parameter) -> f32 {
+        match *self.staged() {
+            Some(staged) if !self.overridden[parameter as usize].load(Ordering::Acquire) => {
+                staged[parameter as usize]
+            }
+            _ => self.get(parameter),
+        }
+    }
+
+    /// Make a staged preset or chunk what processing sees. Only called from
+    /// the audio thread, between blocks, which never waits on the lock.
+    pub(crate) fn apply_staged(&self) {
+        let Ok(mut staged) = self.staged.try_lock() else {
+            return;
+        };
+        if let Some(values) = staged.take() {
+            for ((parameter, overridden), value) in
+                self.values.iter().zip(&self.overridden).zip(values)
+            {
+                if !overridden.swap(false, Ordering::AcqRel) {
+                    parameter.set(value);
+                }
+            }
+        }
+    }
+
+    /// Stage `values` to be applied by `apply_staged`, over anything set
+    /// before.
+    fn stage(&self, values: [f32; Parameter::COUNT]) {
+        let mut staged = self.staged();
+        for overridden in &self.overridden {
+            overridden.store(false, Ordering::Release);
+        }
+        *staged = Some(values);
+    }
+
+    fn staged(&self) -> MutexGuard<'_, Option<[f32; Parameter::COUNT]>> {
+        // Nothing panics while holding it, and the values are plain floats
+        // either way.
+        self.staged.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether `data` is a note message to echo: one the engine listened to,
+    /// while MIDI thru is on.
+    fn is_thru_note(&self, data: [u8; 3]) -> bool {
+        let (status, channel) = (data[0] & 0xF0, data[0] & 0x0F);
+        self.switch(Parameter::MidiThru)
+            && engine::channel_message(data).is_some()
+            && matches!(status, 0x80 | 0x90)
+            && self.channel_filter().is_none_or(|filter| filter == channel)
+    }
+
+    /// Linear output gain factor.
+    fn gain(&self) -> f64 {
+        db_to_amplitude(gain_to_db(self.get(Parameter::Gain)))
+    }
+
+    /// Zero-based MIDI channel to respond to, `None` for all channels.
+    fn channel_filter(&self) -> Option<u8> {
+        channel_filter(self.get(Parameter::Channel))
+    }
+
+    fn octave(&self) -> i32 {
+        octave(self.get(Parameter::Octave))
+    }
+
+    fn pan(&self) -> f64 {
+        pan(self.get(Parameter::Pan))
+    }
+
+    fn width(&self) -> f64 {
+        width(self.get(Parameter::Width))
+    }
+
+    fn division(&self) -> usize {
+        division(self.get(Parameter::Division))
+    }
+
+    fn speed(&self) -> f64 {
+        speed(self.get(Parameter::Speed))
+    }
+
+    fn bits(&self) -> Option<f64> {
+        bits(self.get(Parameter::Bits))
+    }
+
+    fn downsample(&self) -> usize {
+        downsample(self.get(Parameter::Downsample))
+    }
+
+    fn periods(&self) -> usize {
+        periods(self.get(Parameter::Periods))
+    }
+
+    /// Loop level for a note played with `velocity`.
+    fn velocity_gain(&self, velocity: u8) -> f64 {
+        let sensitivity = f64::from(self.get(Parameter::Velocity));
+        1.0 - sensitivity * (1.0 - f64::from(velocity) / 127.0)
+    }
+
+    fn xfade_ms(&self) -> f32 {
+        self.get(Parameter::Crossfade) * MAX_XFADE_MS
+    }
+
+    /// Release cross-fade for a key let go with note-off `velocity`.
+    fn release_xfade_ms(&self, velocity: u8) -> f32 {
+        if !self.switch(Parameter::ReleaseVelocity) {
+            return self.xfade_ms();
+        }
+        let fastest = self.get(Parameter::MinCrossfade) * MAX_XFADE_MS;
+        let speed = f32::from(velocity) / 127.0;
+        self.xfade_ms() + speed * (fastest - self.xfade_ms())
+    }
+
+    fn tuning_hz(&self) -> f64 {
+        tuning_hz(self.get(Parameter::Tuning))
+    }
+
+    /// Frequency of `pitch` under the tuning table if there's one, or equal
+    /// temperament at the reference frequency otherwise.
+    fn note_freq(&self, pitch: u8) -> f64 {
+        if self.tuning_table_enabled.load(Ordering::Acquire) {
+            let bits =
+                self.tuning_table[usize::from(pitch) % tuning::NOTES].load(Ordering::Relaxed);
+            f64::from_bits(bits)
+        } else {
+            midi_pitch_to_freq(pitch, self.tuning_hz())
+        }
+    }
+
+    /// Tuning table the notes follow, if any.
+    pub fn tuning_table(&self) -> Option<TuningTable> {
+        if !self.tuning_table_enabled.load(Ordering::Acquire) {
+            return None;
+        }
+        let freqs = self
+            .tuning_table
+            .each_ref()
+            .map(|freq| f64::from_bits(freq.load(Ordering::Relaxed)));
+        TuningTable::from_freqs(freqs)
+    }
+
+    /// Tune every note by `table`, for one parsed with
+    /// `TuningTable::from_scl`, or go back to equal temperament at the
+    /// tuning reference with `None`. The table is saved with the presets.
+    pub fn set_tuning_table(&self, table: Option<&TuningTable>) {
+        // Disable the table while it's rewritten, so a note-on never sees
+        // it half updated.
+        self.tuning_table_enabled.store(false, Ordering::Release);
+        if let Some(table) = table {
+            for (freq, value) in self.tuning_table.iter().zip(table.freqs()) {
+                freq.store(value.to_bits(), Ordering::Relaxed);
+            }
+            self.tuning_table_enabled.store(true, Ordering::Release);
+        }
+    }
+
+    fn freeze_mode(&self) -> FreezeMode {
+        match choice(self.get(Parameter::Mode), 2) {
+            0 => FreezeMode::Loop,
+            _ => FreezeMode::Granular,
+        }
+    }
+
+    fn grain_ms(&self) -> f32 {
+        grain_ms(self.get(Parameter::GrainSize)) as f32
+    }
+
+    fn grain_density(&self) -> f64 {
+        grain_density(self.get(Parameter::GrainDensity))
+    }
+
+    fn grain_spread(&self) -> f64 {
+        f64::from(self.get(Parameter::GrainSpread))
+    }
+
+    fn attack_ms(&self) -> f32 {
+        envelope_ms(self.get(Parameter::Attack))
+    }
+
+    fn release_ms(&self) -> f32 {
+        envelope_ms(self.get(Parameter::Release))
+    }
+
+    fn max_hold_seconds(&self) -> Option<f32> {
+        hold_seconds(self.get(Parameter::MaxHold))
+    }
+
+    fn max_hold_beats(&self) -> Option<usize> {
+        hold_beats(self.get(Parameter::MaxHoldBeats))
+    }
+
+    fn glide_ms(&self) -> f32 {
+        envelope_ms(self.get(Parameter::Glide))
+    }
+
+    fn feedback(&self) -> f64 {
+        f64::from(self.get(Parameter::Feedback)) * MAX_FEEDBACK
+    }
+
+    fn smooth_ms(&self) -> f32 {
+        self.get(Parameter::Smoothing) * MAX_SMOOTH_MS
+    }
+
+    fn haas_ms(&self) -> f32 {
+        self.get(Parameter::Haas) * MAX_HAAS_MS
+    }
+
+    fn trigger_window_ms(&self) -> Option<f32> {
+        trigger_window_ms(self.get(Parameter::TriggerWindow))
+    }
+
+    fn attack_hold_ms(&self) -> f32 {
+        attack_hold_ms(self.get(Parameter::AttackHold))
+    }
+
+    fn channel_spread_ms(&self) -> f32 {
+        self.get(Parameter::ChannelSpread) * MAX_CHANNEL_SPREAD_MS
+    }
+
+    fn to_chunk(&self) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(16 + 4 * Parameter::COUNT + 8 * tuning::NOTES);
+        chunk.extend_from_slice(&CHUNK_MAGIC);
+        chunk.extend_from_slice(&CHUNK_VERSION.to_le_bytes());
+        chunk.extend_from_slice(&(Parameter::COUNT as u32).to_le_bytes());
+        for parameter in Parameter::ALL {
+            chunk.extend_from_slice(&self.value(parameter).to_le_bytes());
+        }
+        match self.tuning_table() {
+            Some(table) => {
+                chunk.extend_from_slice(&(tuning::NOTES as u32).to_le_bytes());
+                for freq in table.freqs() {
+                    chunk.extend_from_slice(&freq.to_le_bytes());
+                }
             }
+            None => chunk.extend_from_slice(&0u32.to_le_bytes()),
+        }
+        chunk
+    }
+
+    /// Restore parameters saved by `to_chunk`, leaving them untouched if the
+    /// chunk can't be understood. Parameters the chunk predates get defaults.
+    fn load_chunk(&self, chunk: &[u8]) {
+        if let Some(state) = parse_chunk(chunk) {
+            self.stage(Parameter::ALL.map(|parameter| {
+                let value = state.values.get(parameter as usize).copied();
+                value.unwrap_or(parameter.default_value())
+            }));
+            self.set_tuning_table(state.tuning_table.as_ref());
+            self.changed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Set every parameter to the factory preset at `index`, if there's
+    /// one, keeping the tuning table.
+    fn load_preset(&self, index: usize) {
+        if let Some(preset) = PRESETS.get(index) {
+            self.stage(Parameter::ALL.map(|parameter| preset.value(parameter)));
+            self.preset.store(index, Ordering::Release);
+            self.changed.store(true, Ordering::Release);
+        }
+    }
+
+    fn switch(&self, parameter: Parameter) -> bool {
+        self.get(parameter) >= 0.5
+    }
+
+    fn curve(&self) -> CrossfadeCurve {
+        match choice(self.get(Parameter::Curve), 2) {
+            0 => CrossfadeCurve::Linear,
+            _ => CrossfadeCurve::EqualPower,
+        }
+    }
+
+    fn note_priority(&self) -> NotePriority {
+        match choice(self.get(Parameter::Priority), 3) {
+            0 => NotePriority::Last,
+            1 => NotePriority::Highest,
+            _ => NotePriority::Lowest,
+        }
+    }
+
+    fn mod_target(&self) -> ModTarget {
+        match choice(self.get(Parameter::ModTarget), 3) {
+            0 => ModTarget::Speed,
+            1 => ModTarget::Mix,
+            _ => ModTarget::Cutoff,
+        }
+    }
+
+    fn filter_kind(&self) -> Option<FilterKind> {
+        match choice(self.get(Parameter::Filter), 3) {
+            0 => None,
+            1 => Some(FilterKind::LowPass),
+            _ => Some(FilterKind::HighPass),
+        }
+    }
+
+    fn limiter(&self) -> Option<Limiter> {
+        match choice(self.get(Parameter::Limiter), 3) {
+            0 => None,
+            1 => Some(Limiter::Linked),
+            _ => Some(Limiter::PerChannel),
+        }
+    }
+
+    fn threshold(&self) -> f64 {
+        db_to_amplitude(threshold_db(self.get(Parameter::Threshold)))
+    }
+
+    fn release_threshold(&self) -> f64 {
+        db_to_amplitude(threshold_db(self.get(Parameter::ReleaseThreshold)))
+    }
+
+    fn bend_range(&self) -> f64 {
+        bend_range(self.get(Parameter::BendRange)) as f64
+    }
+
+    fn seed(&self) -> u32 {
+        seed(self.get(Parameter::Seed))
+    }
+
+    fn reset_controller(&self) -> Option<u8> {
+        reset_controller(self.get(Parameter::ResetController))
+    }
+
+    fn trigger_note(&self) -> u8 {
+        trigger_note(self.get(Parameter::TriggerNote))
+    }
+
+    fn input_gain(&self) -> f64 {
+        db_to_amplitude(input_gain_db(self.get(Parameter::InputGain)))
+    }
+
+    fn ceiling(&self) -> f64 {
+        db_to_amplitude(ceiling_db(self.get(Parameter::Ceiling)))
+    }
+
+    fn cutoff(&self) -> f64 {
+        cutoff(self.get(Parameter::Cutoff))
+    }
+
+    fn resonance(&self) -> f64 {
+        resonance(self.get(Parameter::Resonance))
+    }
+
+    /// Linear at 1x, cubic once the extra reads are paid for anyway.
+    fn interpolation(&self) -> Interpolation {
+        match self.oversampling() {
+            1 => Interpolation::Linear,
+            _ => Interpolation::Cubic,
+        }
+    }
+
+    /// Loop reads per frame, 1, 2 or 4.
+    fn oversampling(&self) -> usize {
+        1 << choice(self.get(Parameter::Quality), 3)
+    }
+
+    fn stutter_rate(&self) -> Option<f64> {
+        stutter_rate(self.get(Parameter::StutterRate))
+    }
+
+    /// Length of a stutter cycle in beats when synced.
+    fn stutter_beats(&self) -> f64 {
+        stutter_division(self.get(Parameter::StutterDivision)).1
+    }
+
+    fn stutter_depth(&self) -> f64 {
+        f64::from(self.get(Parameter::StutterDepth))
+    }
+
+    fn envelope_octaves(&self) -> f64 {
+        envelope_octaves(self.get(Parameter::EnvelopeDepth))
+    }
+
+    fn envelope_attack_ms(&self) -> f32 {
+        envelope_ms(self.get(Parameter::EnvelopeAttack))
+    }
+
+    fn envelope_release_ms(&self) -> f32 {
+        envelope_ms(self.get(Parameter::EnvelopeRelease))
+    }
+
+    fn shimmer_level(&self) -> f64 {
+        f64::from(self.get(Parameter::ShimmerLevel))
+    }
+
+    /// Share of the loop replaced by its octave up copy per second, bounded
+    /// so it takes a while to climb.
+    fn shimmer_feedback(&self) -> f64 {
+        f64::from(self.get(Parameter::ShimmerFeedback)) * MAX_SHIMMER_FEEDBACK
+    }
+
+    fn level_match(&self) -> f64 {
+        f64::from(self.get(Parameter::LevelMatch))
+    }
+
+    fn capture_source(&self) -> CaptureSource {
+        match choice(self.get(Parameter::CaptureSource), 2) {
+            0 => CaptureSource::Input,
+            _ => CaptureSource::FrozenOutput,
+        }
+    }
+
+    fn seam_mode(&self) -> SeamMode {
+        match choice(self.get(Parameter::Seam), 2) {
+            0 => SeamMode::Average,
+            _ => SeamMode::OverlapAdd,
+        }
+    }
+
+    fn direction(&self) -> PlayDirection {
+        if self.switch(Parameter::PingPong) {
+            PlayDirection::PingPong
+        } else if self.switch(Parameter::Reverse) {
+            PlayDirection::Reverse
+        } else {
+            PlayDirection::Forward
         }
     }
 }
 
+impl Default for ZamerzikaParameters {
+    fn default() -> Self {
+        ZamerzikaParameters {
+            values: Parameter::ALL.map(|parameter| AtomicFloat::new(parameter.default_value())),
+            tuning_table_enabled: AtomicBool::new(false),
+            tuning_table: [(); tuning::NOTES].map(|_| AtomicU64::new(0)),
+            changed: AtomicBool::new(false),
+            preset: AtomicUsize::new(0),
+            staged: Mutex::new(None),
+            overridden: Parameter::ALL.map(|_| AtomicBool::new(false)),
+            meters: Meters::default(),
+        }
+    }
+}
+
+impl PluginParameters for ZamerzikaParameters {
+    fn get_parameter(&self, index: i32) -> f32 {
+        Parameter::from_index(index).map_or(0.0, |parameter| self.value(parameter))
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        if let Some(parameter) = Parameter::from_index(index) {
+            self.set(parameter, value);
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        Parameter::from_index(index)
+            .map_or_else(String::new, |parameter| parameter.name().to_string())
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        Parameter::from_index(index).map_or_else(String::new, |parameter| {
+            parameter.label(self.value(parameter)).to_string()
+        })
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        Parameter::from_index(index).map_or_else(String::new, |parameter| {
+            parameter.text(self.value(parameter))
+        })
+    }
+
+    fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        let parsed = Parameter::from_index(index)
+            .and_then(|parameter| Some((parameter, parameter.parse(&text)?)));
+        if let Some((parameter, value)) = parsed {
+            self.set(parameter, value);
+        }
+        parsed.is_some()
+    }
+
+    fn change_preset(&self, preset: i32) {
+        if let Ok(index) = usize::try_from(preset) {
+            self.load_preset(index);
+        }
+    }
+
+    fn get_preset_num(&self) -> i32 {
+        self.preset.load(Ordering::Acquire) as _
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        usize::try_from(preset)
+            .ok()
+            .and_then(|index| PRESETS.get(index))
+            .map_or_else(String::new, |preset| preset.name.to_string())
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.to_chunk()
+    }
+
+    // There's a single program, so the bank is the same as the preset.
+    fn get_bank_data(&self) -> Vec<u8> {
+        self.to_chunk()
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        self.load_chunk(data);
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        self.load_chunk(data);
+    }
+}
+
+/// Starting point for a sound: the defaults but for a few parameters.
+struct Preset {
+    name: &'static str,
+    /// Normalized values differing from the defaults.
+    values: &'static [(Parameter, f32)],
+}
+
+impl Preset {
+    fn value(&self, parameter: Parameter) -> f32 {
+        self.values
+            .iter()
+            .find(|&&(changed, _)| changed == parameter)
+            .map_or(parameter.default_value(), |&(_, value)| value)
+    }
+}
+
+/// Factory presets in the order hosts list them, the first being what a new
+/// instance starts with.
+const PRESETS: [Preset; 4] = [
+    Preset {
+        name: "Clean Loop",
+        values: &[],
+    },
+    Preset {
+        name: "Granular Pad",
+        values: &[
+            (Parameter::Mode, 1.0),
+            (Parameter::GrainSize, 0.7),
+            (Parameter::GrainDensity, 0.6),
+            (Parameter::GrainSpread, 0.5),
+            (Parameter::Attack, 0.4),
+            (Parameter::Release, 0.6),
+            (Parameter::Width, 0.75),
+        ],
+    },
+    Preset {
+        name: "Reverse Shimmer",
+        values: &[
+            (Parameter::Reverse, 1.0),
+            (Parameter::ShimmerLevel, 0.4),
+            (Parameter::ShimmerFeedback, 0.3),
+            (Parameter::Attack, 0.2),
+            (Parameter::Release, 0.5),
+        ],
+    },
+    Preset {
+        name: "Wide Bounce",
+        values: &[
+            (Parameter::PingPong, 1.0),
+            (Parameter::ChannelSpread, 0.5),
+            (Parameter::Haas, 0.3),
+            (Parameter::Width, 0.75),
+        ],
+    },
+];
+
+/// Identifies chunks produced by `ZamerzikaParameters::to_chunk`.
+const CHUNK_MAGIC: [u8; 4] = *b"ZMRZ";
+/// Bump when the chunk layout changes, and teach `parse_chunk` the old one.
+const CHUNK_VERSION: u32 = 2;
+
+/// Everything a preset chunk holds.
+struct PresetState {
+    /// Normalized values in parameter index order.
+    values: Vec<f32>,
+    tuning_table: Option<TuningTable>,
+}
+
+/// Contents of a chunk, if it's well-formed and of a known version.
+///
+/// Layout, little-endian: magic, version, `u32` count of values and that
+/// many `f32` values in parameter index order. Since version 2 those are
+/// followed by a `u32` count of tuning table notes, either 0 or
+/// `tuning::NOTES`, and that many `f64` frequencies.
+fn parse_chunk(chunk: &[u8]) -> Option<PresetState> {
+    let field =
+        |offset: usize| -> Option<[u8; 4]> { chunk.get(offset..offset + 4)?.try_into().ok() };
+    let wide_field =
+        |offset: usize| -> Option<[u8; 8]> { chunk.get(offset..offset + 8)?.try_into().ok() };
+    if field(0)? != CHUNK_MAGIC {
+        return None;
+    }
+    let version = u32::from_le_bytes(field(4)?);
+    if !(1..=CHUNK_VERSION).contains(&version) {
+        return None;
+    }
+    let count = u32::from_le_bytes(field(8)?) as usize;
+    let values = (0..count)
+        .map(|index| {
+            let value = f32::from_le_bytes(field(12 + 4 * index)?);
+            (0.0..=1.0).contains(&value).then_some(value)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let mut end = 12 + 4 * count;
+    let mut tuning_table = None;
+    if version >= 2 {
+        let notes = u32::from_le_bytes(field(end)?) as usize;
+        end += 4;
+        match notes {
+            0 => (),
+            tuning::NOTES => {
+                let mut freqs = [0.0; tuning::NOTES];
+                for (note, freq) in freqs.iter_mut().enumerate() {
+                    *freq = f64::from_le_bytes(wide_field(end + 8 * note)?);
+                }
+                end += 8 * tuning::NOTES;
+                tuning_table = Some(TuningTable::from_freqs(freqs)?);
+            }
+            _ => return None,
+        }
+    }
+    (chunk.len() == end).then_some(PresetState {
+        values,
+        tuning_table,
+    })
+}
+
 /// Convert the midi note's pitch into the equivalent frequency.
 ///
-/// This function assumes A4 is 440 Hz.
-fn midi_pitch_to_freq(pitch: u8) -> f64 {
+/// This function assumes equal temperament with A4 tuned to `a4_freq` Hz.
+fn midi_pitch_to_freq(pitch: u8, a4_freq: f64) -> f64 {
     const A4_PITCH: i8 = 69;
-    const A4_FREQ: f64 = 440.0;
 
     // Midi notes can be 0-127
-    ((f64::from(pitch as i8 - A4_PITCH)) / 12.).exp2() * A4_FREQ
+    ((f64::from(pitch as i8 - A4_PITCH)) / 12.).exp2() * a4_freq
 }
 
-#[derive(Default)]
-struct RingBuffer {
-    read_cursor: usize,
-    write_cursor: usize,
-    len: usize,
-    data: Vec<f64>,
-}
+plugin_main!(Zamerzika);
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    const BLOCK: usize = 256;
+
+    fn plugin() -> Zamerzika {
+        Zamerzika::new(HostCallback::default())
+    }
+
+    fn midi(delta_frames: i32, data: [u8; 3]) -> MidiEvent {
+        MidiEvent {
+            data,
+            delta_frames,
+            live: true,
+            note_length: None,
+            note_offset: None,
+            detune: 0,
+            note_off_velocity: 0,
+        }
+    }
+
+    /// A different sine on every input channel.
+    fn tone(channel: usize, frame: usize) -> f64 {
+        let freq = 220.0 + 110.0 * channel as f64;
+        0.5 * (TAU * freq * frame as f64 / 48_000.0).sin()
+    }
 
-impl RingBuffer {
-    fn write(&mut self, sample: f64) {
-        self.data[self.write_cursor] = sample;
-        self.write_cursor += 1;
-        if self.write_cursor >= self.len {
-            self.write_cursor = 0;
+    /// Output per channel of six blocks of `tone` on buses of `(inputs,
+    /// outputs)`, with A3 held from the first block into the fourth and
+    /// `round` applied to every sample going in and out.
+    fn render_blocks(
+        plugin: &mut Zamerzika,
+        (inputs, outputs): (usize, usize),
+        round: impl Fn(f64) -> f64,
+    ) -> Vec<Vec<f64>> {
+        let mut rendered = vec![vec![0.0; 6 * BLOCK]; outputs];
+        for block in 0..6 {
+            match block {
+                0 => plugin.pending_events.push(midi(100, [0x90, 57, 100])),
+                3 => plugin.pending_events.push(midi(17, [0x80, 57, 64])),
+                _ => (),
+            }
+            let start = block * BLOCK;
+            plugin.process_block(
+                BLOCK,
+                (inputs, outputs),
+                |channel, frame| round(tone(channel, start + frame)),
+                |channel, frame, sample| rendered[channel][start + frame] = round(sample),
+            );
         }
+        rendered
+    }
+
+    #[test]
+    fn installed_tuning_table_tunes_the_notes_and_is_saved() {
+        let params = ZamerzikaParameters::default();
+        let table = TuningTable::from_scl("Fifths\n 2\n 3/2\n 2/1\n", 60, 260.0).unwrap();
+        params.set_tuning_table(Some(&table));
+        assert_eq!(params.note_freq(61), 390.0);
+        let restored = ZamerzikaParameters::default();
+        restored.load_chunk(&params.to_chunk());
+        assert_eq!(restored.tuning_table(), Some(table));
+        params.set_tuning_table(None);
+        assert_eq!(params.note_freq(69), 440.0);
+    }
+
+    #[test]
+    fn loading_a_preset_applies_between_blocks_and_yields_to_later_changes() {
+        let params = ZamerzikaParameters::default();
+        params.load_preset(1);
+        // The host sees the whole preset at once, processing none of it yet.
+        assert_eq!(params.value(Parameter::Mode), 1.0);
+        assert_eq!(params.value(Parameter::Width), 0.75);
+        assert_eq!(params.get(Parameter::Mode), Parameter::Mode.default_value());
+        assert_eq!(
+            params.get(Parameter::Width),
+            Parameter::Width.default_value()
+        );
+        params.set(Parameter::Width, 0.25);
+        params.apply_staged();
+        assert_eq!(params.get(Parameter::Mode), 1.0);
+        assert_eq!(params.get(Parameter::Width), 0.25);
+        assert_eq!(params.value(Parameter::Width), 0.25);
     }
 
-    fn read(&mut self) -> f64 {
-        let result = self.data[self.read_cursor];
-        self.read_cursor += 1;
-        if self.read_cursor >= self.len {
-            self.read_cursor = 0;
+    #[test]
+    fn setting_while_the_stage_is_locked_outlasts_applying_it() {
+        let params = ZamerzikaParameters::default();
+        params.load_preset(1);
+        {
+            let _loading = params.staged();
+            params.set(Parameter::Width, 0.25);
         }
-        result
+        assert_eq!(params.value(Parameter::Width), 0.25);
+        params.apply_staged();
+        assert_eq!(params.get(Parameter::Mode), 1.0);
+        assert_eq!(params.get(Parameter::Width), 0.25);
+        // Nor does it hold a parameter back from the next preset.
+        params.load_preset(1);
+        params.apply_staged();
+        assert_eq!(params.get(Parameter::Width), 0.75);
     }
 
-    fn resize(&mut self, new_len: usize, value: f64) {
-        self.read_cursor = 0;
-        self.write_cursor = 0;
-        self.len = new_len;
-        self.data.resize(new_len, value);
+    #[test]
+    fn f32_and_f64_processing_match() {
+        let single = render_blocks(&mut plugin(), (2, 2), |sample| f64::from(sample as f32));
+        let double = render_blocks(&mut plugin(), (2, 2), |sample| sample);
+        // Only rounding to `f32` on the way in and out tells the two apart.
+        let tolerance = 4.0 * f64::from(f32::EPSILON);
+        for (channel, (single, double)) in single.iter().zip(&double).enumerate() {
+            for (frame, (single, double)) in single.iter().zip(double).enumerate() {
+                assert!(
+                    (single - double).abs() <= tolerance,
+                    "channel {channel} frame {frame}: {single} vs {double}"
+                );
+            }
+        }
+        // Which wouldn't mean much if nothing froze.
+        let frozen =
+            (BLOCK..3 * BLOCK).any(|frame| (double[0][frame] - tone(0, frame)).abs() > 0.1);
+        assert!(frozen);
     }
 
-    fn open_window(&mut self, window_size: usize) {
-        let end = self.write_cursor;
-        let len = self.len;
-        let start = (end + len - window_size) % len;
-        self.read_cursor = start;
+    #[test]
+    fn mono_and_four_channel_buses_process_their_first_two_channels() {
+        for channels in [1, 4] {
+            let rendered = render_blocks(&mut plugin(), (channels, channels), |sample| sample);
+            let processed = channels.min(CHANNELS);
+            for (channel, output) in rendered.iter().enumerate().take(processed) {
+                let dry: Vec<f64> = (0..100).map(|frame| tone(channel, frame)).collect();
+                assert_eq!(output[..100], dry, "{channels} channel {channel}");
+                let frozen = (BLOCK..3 * BLOCK)
+                    .any(|frame| (output[frame] - tone(channel, frame)).abs() > 0.1);
+                assert!(frozen, "{channels} channel {channel}");
+            }
+            // The rest, which aren't processed yet, are silent.
+            for output in &rendered[processed..] {
+                assert!(output.iter().all(|&sample| sample == 0.0), "{channels}");
+            }
+        }
     }
 
-    fn smooth(&mut self, depth: usize) {
-        let depth = depth.min(self.len);
-        let offset = self.read_cursor + self.len;
-        for i in offset..(offset + depth) {
-            let current = i % self.len;
-            let previous = (i - 1) % self.len;
-            self.data[current] = 0.5 * (self.data[current] + self.data[previous]);
+    #[test]
+    fn resuming_widens_the_engine_to_the_bus_processed_with() {
+        let mut plugin = plugin();
+        render_blocks(&mut plugin, (4, 4), |sample| sample);
+        plugin.resume();
+        assert_eq!(plugin.engine.channels(), 4);
+        assert_eq!(plugin.get_info().outputs, 4);
+        let rendered = render_blocks(&mut plugin, (4, 4), |sample| sample);
+        for (channel, output) in rendered.iter().enumerate() {
+            let dry: Vec<f64> = (0..100).map(|frame| tone(channel, frame)).collect();
+            assert_eq!(output[..100], dry, "channel {channel}");
+            let frozen =
+                (BLOCK..3 * BLOCK).any(|frame| (output[frame] - tone(channel, frame)).abs() > 0.1);
+            assert!(frozen, "channel {channel}");
         }
     }
-}
 
-plugin_main!(Zamerzika);
+    #[test]
+    fn mono_input_plays_on_every_output() {
+        let mono = render_blocks(&mut plugin(), (1, 1), |sample| sample);
+        let stereo = render_blocks(&mut plugin(), (1, 2), |sample| sample);
+        let dry: Vec<f64> = (0..100).map(|frame| tone(0, frame)).collect();
+        for output in mono.iter().chain(&stereo) {
+            assert_eq!(output[..100], dry);
+        }
+        let frozen =
+            (BLOCK..3 * BLOCK).any(|frame| (stereo[0][frame] - tone(0, frame)).abs() > 0.1);
+        assert!(frozen);
+        // Frozen alike on both sides, and just as a mono output has it, but
+        // for rounding.
+        for frame in 0..6 * BLOCK {
+            let (left, right, mono) = (stereo[0][frame], stereo[1][frame], mono[0][frame]);
+            assert!(
+                (left - right).abs() < 1e-9,
+                "frame {frame}: {left} vs {right}"
+            );
+            assert!(
+                (left - mono).abs() < 1e-9,
+                "frame {frame}: {left} vs {mono}"
+            );
+        }
+    }
+
+    #[test]
+    fn parameters_parse_what_they_show() {
+        for parameter in Parameter::ALL {
+            for value in [
+                0.0,
+                0.2,
+                0.25,
+                0.5,
+                0.7,
+                0.75,
+                1.0,
+                parameter.default_value(),
+            ] {
+                let display = parameter.display(value);
+                for text in [display.clone(), parameter.text(value)] {
+                    let parsed = parameter.parse(&text);
+                    let shown = parsed.map(|parsed| parameter.display(parsed));
+                    assert_eq!(
+                        shown.as_ref(),
+                        Some(&display),
+                        "{} {text}",
+                        parameter.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parameters_parse_numbers_between_what_they_show() {
+        let shown = |parameter: Parameter, text| {
+            parameter.parse(text).map(|value| parameter.display(value))
+        };
+        assert_eq!(shown(Parameter::Gain, "-6 dB").as_deref(), Some("-6.0 dB"));
+        assert_eq!(
+            shown(Parameter::Cutoff, "1.5 kHz").as_deref(),
+            Some("1.50 kHz")
+        );
+        assert_eq!(
+            shown(Parameter::Cutoff, "1500").as_deref(),
+            Some("1.50 kHz")
+        );
+        assert_eq!(shown(Parameter::Pan, "l 50").as_deref(), Some("L 50"));
+        assert_eq!(shown(Parameter::Channel, "omni").as_deref(), Some("Omni"));
+        assert_eq!(shown(Parameter::Octave, "1").as_deref(), Some("+1"));
+        assert_eq!(Parameter::Gain.parse("loud"), None);
+        assert_eq!(Parameter::Mode.parse("3"), None);
+    }
+}
@@ -0,0 +1,549 @@
+//! Circular sample buffers for recording input and looping frozen windows.
+
+/// Samples the turnarounds of ping-pong reads fade over.
+const TURN_FADE: usize = 16;
+
+/// `phase` wrapped into `[0, len)`.
+pub fn wrap_phase(phase: f64, len: f64) -> f64 {
+    let phase = phase.rem_euclid(len);
+    // Guard against rounding up to `len` itself.
+    if phase >= len {
+        0.0
+    } else {
+        phase
+    }
+}
+
+/// Way a buffer is played through.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PlayDirection {
+    Forward,
+    Reverse,
+    /// Forward and back again in turn, bouncing off either end.
+    PingPong,
+}
+
+impl PlayDirection {
+    pub fn reversed(self) -> PlayDirection {
+        match self {
+            PlayDirection::Forward => PlayDirection::Reverse,
+            PlayDirection::Reverse => PlayDirection::Forward,
+            PlayDirection::PingPong => PlayDirection::PingPong,
+        }
+    }
+}
+
+/// How reads between samples make up the signal there.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight lines, cheap but dulling the highs at most rates.
+    Linear,
+    /// 4-point Catmull-Rom spline through the neighbouring samples.
+    Cubic,
+}
+
+/// Circular sample buffer. Until it's given a length it reads silence and
+/// ignores writes.
+#[derive(Default)]
+pub struct RingBuffer {
+    read_cursor: usize,
+    /// Fractional counterpart of `read_cursor` used by `read_interp`.
+    read_phase: f64,
+    write_cursor: usize,
+    len: usize,
+    data: Vec<f64>,
+    /// Samples which preceded the window at the start of the buffer, faded
+    /// in over its end, see `capture_seam`.
+    seam: Vec<f64>,
+    /// Ping-pong reads are on their way back to the start.
+    backwards: bool,
+    turn: Turn,
+}
+
+/// Fade into the way back after a ping-pong read turns around, from the
+/// way back mirrored about the sample turned at: that carries on the slope
+/// the read arrived with, bending it round rather than flipping it at once.
+#[derive(Clone, Copy, Default)]
+struct Turn {
+    /// Sample turned at.
+    edge: f64,
+    /// Samples left of the fade, out of `TURN_FADE`.
+    countdown: usize,
+}
+
+impl RingBuffer {
+    /// Buffer of `len` silent samples, with room reserved to capture a seam
+    /// just as long without allocating.
+    pub fn new(len: usize) -> Self {
+        let mut buffer = RingBuffer::default();
+        buffer.resize(len, 0.0);
+        buffer.seam.reserve(len);
+        buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fractional read position, see `read_interp`.
+    pub fn read_phase(&self) -> f64 {
+        self.read_phase
+    }
+
+    /// Store `sample` at the write cursor and advance it, wrapping around.
+    pub fn write(&mut self, sample: f64) {
+        if self.is_empty() {
+            return;
+        }
+        self.data[self.write_cursor] = sample;
+        self.write_cursor += 1;
+        if self.write_cursor >= self.len {
+            self.write_cursor = 0;
+        }
+    }
+
+    /// Sample at the read cursor, advancing it by one and wrapping around.
+    pub fn read(&mut self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let result = self.data[self.read_cursor];
+        self.read_cursor += 1;
+        if self.read_cursor >= self.len {
+            self.read_cursor = 0;
+        }
+        self.read_phase = self.read_cursor as f64;
+        result
+    }
+
+    /// Read at a fractional position, interpolating between neighbouring
+    /// samples, and advance the position by `stride`.
+    ///
+    /// Shares the position with `read`: `read_cursor` follows the integer
+    /// part of the phase, so the two can be mixed on the same buffer.
+    pub fn read_interp(&mut self, stride: f64, interpolation: Interpolation) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let result = self.peek_with(self.read_phase, interpolation);
+        self.read_phase = wrap_phase(self.read_phase + stride, self.len as f64);
+        self.read_cursor = self.read_phase as usize;
+        result
+    }
+
+    /// Like `read_interp`, but turning around at either end of the buffer
+    /// instead of wrapping, see `Turn` for how that doesn't click.
+    pub fn read_ping_pong(&mut self, stride: f64, interpolation: Interpolation) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let result = self.peek_with(self.read_phase, interpolation);
+        let result = match self.turn.countdown {
+            0 => result,
+            countdown => {
+                self.turn.countdown -= 1;
+                let alpha = countdown as f64 / (TURN_FADE + 1) as f64;
+                result + 2.0 * alpha * (self.turn.edge - result)
+            }
+        };
+        let last = (self.len - 1) as f64;
+        let step = if self.backwards { -stride } else { stride };
+        let mut phase = self.read_phase + step;
+        if phase > last || phase < 0.0 {
+            // Reflect off the end sample, so it's the one turned at.
+            let edge = if phase > last { last } else { 0.0 };
+            self.turn = Turn {
+                edge: self.at(edge as usize),
+                countdown: TURN_FADE,
+            };
+            phase = 2.0 * edge - phase;
+            self.backwards = !self.backwards;
+        }
+        self.read_phase = phase.clamp(0.0, last);
+        self.read_cursor = self.read_phase as usize;
+        result
+    }
+
+    /// `peek` or `peek_cubic`, according to `interpolation`.
+    fn peek_with(&self, phase: f64, interpolation: Interpolation) -> f64 {
+        match interpolation {
+            Interpolation::Linear => self.peek(phase),
+            Interpolation::Cubic => self.peek_cubic(phase),
+        }
+    }
+
+    /// Linearly interpolated value at fractional position `phase`, which
+    /// must lie in `[0, len)`.
+    pub fn peek(&self, phase: f64) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let index = phase as usize;
+        let fraction = phase - index as f64;
+        let current = self.at(index);
+        // Interpolate across the wrap-around from the last sample to the first.
+        let next = self.at((index + 1) % self.len);
+        current + fraction * (next - current)
+    }
+
+    /// Like `peek`, but interpolating with a Catmull-Rom spline.
+    pub fn peek_cubic(&self, phase: f64) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let len = self.len;
+        let index = phase as usize;
+        let t = phase - index as f64;
+        let before = self.at((index + len - 1) % len);
+        let current = self.at(index);
+        let next = self.at((index + 1) % len);
+        let after = self.at((index + 2) % len);
+        let slope = 0.5 * (next - before);
+        let next_slope = 0.5 * (after - current);
+        let delta = next - current;
+        current
+            + t * (slope
+                + t * (3.0 * delta - 2.0 * slope - next_slope
+                    + t * (slope + next_slope - 2.0 * delta)))
+    }
+
+    /// Mean square of the samples as played.
+    pub fn power(&self) -> f64 {
+        let sum = (0..self.len)
+            .map(|index| self.at(index).powi(2))
+            .sum::<f64>();
+        sum / self.len.max(1) as f64
+    }
+
+    /// Sample at `index` as played, with the seam faded in.
+    fn at(&self, index: usize) -> f64 {
+        let sample = self.data[index];
+        let seam_start = self.len - self.seam.len();
+        if index < seam_start {
+            return sample;
+        }
+        // Reaching the seam fully on the last sample, which it continues
+        // from into the start of the buffer.
+        let alpha = (self.len - index) as f64 / (self.seam.len() + 1) as f64;
+        alpha * sample + (1.0 - alpha) * self.seam[index - seam_start]
+    }
+
+    /// Keep the `depth` samples of `input` leading up to its read cursor,
+    /// which this buffer is about to be filled from, as its seam.
+    ///
+    /// It's limited by the length of this buffer as well as by how far back
+    /// `input` reaches past it.
+    pub fn capture_seam(&mut self, input: &RingBuffer, depth: usize) {
+        let depth = depth.min(self.len).min(input.len.saturating_sub(self.len));
+        let start = input.read_cursor + input.len - depth;
+        self.seam
+            .extend((start..start + depth).map(|index| input.data[index % input.len]));
+    }
+
+    /// Move the sample `read_interp` is about to read towards `sample`,
+    /// by `amount` between 0 (keep) and 1 (replace).
+    pub fn blend_at_read(&mut self, sample: f64, amount: f64) {
+        if self.is_empty() {
+            return;
+        }
+        let index = self.read_phase as usize;
+        self.data[index] += amount * (sample - self.data[index]);
+    }
+
+    /// Like `read_interp`, but walking backwards through the buffer.
+    pub fn read_reverse(&mut self, stride: f64, interpolation: Interpolation) -> f64 {
+        self.read_interp(-stride, interpolation)
+    }
+
+    /// `read_interp`, `read_reverse` or `read_ping_pong`, according to
+    /// `direction`.
+    pub fn read_directed(
+        &mut self,
+        stride: f64,
+        direction: PlayDirection,
+        interpolation: Interpolation,
+    ) -> f64 {
+        match direction {
+            PlayDirection::Forward => self.read_interp(stride, interpolation),
+            PlayDirection::Reverse => self.read_reverse(stride, interpolation),
+            PlayDirection::PingPong => self.read_ping_pong(stride, interpolation),
+        }
+    }
+
+    /// Start over with `new_len` samples of `value`, both cursors at the
+    /// beginning, so the next `new_len` writes fill the buffer in order
+    /// whatever its previous length and contents were.
+    pub fn resize(&mut self, new_len: usize, value: f64) {
+        self.read_cursor = 0;
+        self.read_phase = 0.0;
+        self.write_cursor = 0;
+        self.len = new_len;
+        self.backwards = false;
+        self.turn = Turn::default();
+        self.seam.clear();
+        // Clearing first overwrites the kept samples too, and never gives
+        // up the capacity reserved up front.
+        self.data.clear();
+        self.data.resize(new_len, value);
+    }
+
+    /// Take on the length, contents and cursors of `other`, within the
+    /// capacity already reserved when it's no longer.
+    pub fn copy_from(&mut self, other: &RingBuffer) {
+        self.read_cursor = other.read_cursor;
+        self.read_phase = other.read_phase;
+        self.write_cursor = other.write_cursor;
+        self.len = other.len;
+        self.backwards = other.backwards;
+        self.turn = other.turn;
+        self.data.clear();
+        self.data.extend_from_slice(&other.data[..other.len]);
+        self.seam.clear();
+        self.seam.extend_from_slice(&other.seam);
+    }
+
+    /// Move the read position `frames` samples on, wrapping around.
+    pub fn skip(&mut self, frames: usize) {
+        if self.is_empty() {
+            return;
+        }
+        self.read_cursor = (self.read_cursor + frames) % self.len;
+        self.read_phase = wrap_phase(self.read_phase + frames as f64, self.len as f64);
+    }
+
+    /// Move the read cursor back to the start of the buffer, or to its end
+    /// when reading in reverse.
+    pub fn restart(&mut self, direction: PlayDirection) {
+        self.read_cursor = 0;
+        self.rewind(direction);
+    }
+
+    /// Move the read cursor `window_size` samples behind the write cursor,
+    /// to the start of the latest window written.
+    pub fn open_window(&mut self, window_size: usize) {
+        self.open_window_back(window_size, 0);
+    }
+
+    /// Like `open_window`, but with the window `shift` samples earlier.
+    pub fn open_window_back(&mut self, window_size: usize, shift: usize) {
+        if self.is_empty() {
+            return;
+        }
+        let end = self.write_cursor;
+        let len = self.len;
+        let back = (window_size + shift).min(len);
+        let start = (end + len - back) % len;
+        self.read_cursor = start;
+        self.read_phase = start as f64;
+    }
+
+    /// How far at most `search` samples back from the start of the latest
+    /// `window_size` written the nearest upward zero crossing is, if any.
+    pub fn crossing_before(&self, window_size: usize, search: usize) -> Option<usize> {
+        let len = self.len;
+        // Only as far back as the samples written before the window reach.
+        let search = search.min(len.saturating_sub(window_size));
+        let start = self.write_cursor + len - window_size.min(len);
+        (0..search).find(|shift| {
+            let index = (start - shift) % len;
+            let previous = (start - shift + len - 1) % len;
+            self.data[previous] < 0.0 && self.data[index] >= 0.0
+        })
+    }
+
+    /// Soften the seam at the window start, averaging `depth` samples on
+    /// either side of it with their neighbours across it: first the ones
+    /// played after the seam in `direction`, then the ones played before it.
+    ///
+    /// Zero `depth` leaves the data intact, and it never exceeds the length.
+    pub fn smooth(&mut self, depth: usize, direction: PlayDirection) {
+        // Ping-pong reads only cross the seam while turning, which is
+        // smoothed as a forward loop would be.
+        let direction = match direction {
+            PlayDirection::PingPong => PlayDirection::Forward,
+            direction => direction,
+        };
+        let depth = depth.min(self.len);
+        self.average_from_seam(depth, direction);
+        self.average_from_seam(depth, direction.reversed());
+    }
+
+    /// Average `depth` samples which follow the seam at the window start in
+    /// `direction` with their predecessors, working away from it.
+    fn average_from_seam(&mut self, depth: usize, direction: PlayDirection) {
+        let offset = self.read_cursor + self.len;
+        for i in 0..depth {
+            let (current, previous) = match direction {
+                PlayDirection::Forward | PlayDirection::PingPong => (offset + i, offset + i - 1),
+                PlayDirection::Reverse => (offset - i - 1, offset - i),
+            };
+            let (current, previous) = (current % self.len, previous % self.len);
+            self.data[current] = 0.5 * (self.data[current] + self.data[previous]);
+        }
+    }
+
+    /// Move the fractional read position to the first sample played in
+    /// `direction` from the window start, ping-pong reads setting off
+    /// forwards.
+    pub fn rewind(&mut self, direction: PlayDirection) {
+        if self.is_empty() {
+            return;
+        }
+        self.backwards = false;
+        self.turn = Turn::default();
+        let start = match direction {
+            PlayDirection::Forward | PlayDirection::PingPong => self.read_cursor,
+            PlayDirection::Reverse => (self.read_cursor + self.len - 1) % self.len,
+        };
+        self.read_phase = start as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Buffer of `len` with `samples` written to it in order.
+    fn written(len: usize, samples: impl IntoIterator<Item = f64>) -> RingBuffer {
+        let mut buffer = RingBuffer::new(len);
+        for sample in samples {
+            buffer.write(sample);
+        }
+        buffer
+    }
+
+    fn read(buffer: &mut RingBuffer, count: usize) -> Vec<f64> {
+        (0..count).map(|_| buffer.read()).collect()
+    }
+
+    #[test]
+    fn write_and_read_wrap_around() {
+        // The last two writes wrap round over the first two.
+        let mut buffer = written(4, (1..=6).map(f64::from));
+        assert_eq!(read(&mut buffer, 6), [5.0, 6.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn open_window_starts_window_size_behind_the_write_cursor() {
+        // Ten writes leave the write cursor at 2, with 2 to 9 recorded.
+        let mut buffer = written(8, (0..10).map(f64::from));
+        for window_size in [1, 3, 7, 8] {
+            buffer.open_window(window_size);
+            let expected: Vec<f64> = (10 - window_size..10).map(|value| value as f64).collect();
+            assert_eq!(read(&mut buffer, window_size), expected, "{window_size}");
+        }
+        // No further back than the buffer reaches.
+        buffer.open_window(12);
+        assert_eq!(buffer.read(), 2.0);
+    }
+
+    #[test]
+    fn smooth_depth_is_limited_by_the_length() {
+        let samples = [0.0, 4.0, 0.0, 4.0];
+        let mut deep = written(4, samples);
+        let mut full = written(4, samples);
+        deep.smooth(10, PlayDirection::Forward);
+        full.smooth(4, PlayDirection::Forward);
+        assert_eq!(read(&mut deep, 4), read(&mut full, 4));
+        let mut untouched = written(4, samples);
+        untouched.smooth(0, PlayDirection::Forward);
+        assert_eq!(read(&mut untouched, 4), samples);
+    }
+
+    #[test]
+    fn read_interp_interpolates_from_the_last_sample_to_the_first() {
+        let mut buffer = written(4, [0.0, 10.0, 20.0, 30.0]);
+        buffer.skip(3);
+        let reads: Vec<f64> = (0..4)
+            .map(|_| buffer.read_interp(0.5, Interpolation::Linear))
+            .collect();
+        // Halfway past the last sample is halfway back to the first.
+        assert_eq!(reads, [30.0, 15.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn captures_at_different_sizes_stay_aligned() {
+        // Each sample is the count of those written before it.
+        let mut input = written(64, (0..100).map(f64::from));
+        let mut count = 100;
+        let mut output = RingBuffer::new(64);
+        // A lower note, then higher ones, each window ending with the latest
+        // input and read from its first sample on.
+        for window_size in [12, 5, 9] {
+            input.open_window(window_size);
+            output.resize(window_size, 0.0);
+            for _ in 0..window_size {
+                output.write(input.read());
+            }
+            let expected: Vec<f64> = (count - window_size..count)
+                .map(|value| value as f64)
+                .collect();
+            assert_eq!(read(&mut output, window_size), expected, "{window_size}");
+            assert_eq!(read(&mut output, 1), expected[..1]);
+            let more = count + 7;
+            while count < more {
+                input.write(count as f64);
+                count += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn empty_buffers_read_silence_and_ignore_the_rest() {
+        let mut emptied = written(4, [1.0, 2.0, 3.0, 4.0]);
+        emptied.resize(0, 0.0);
+        for mut buffer in [RingBuffer::default(), RingBuffer::new(0), emptied] {
+            buffer.write(1.0);
+            buffer.open_window(3);
+            buffer.skip(2);
+            buffer.smooth(4, PlayDirection::Forward);
+            buffer.rewind(PlayDirection::Reverse);
+            assert_eq!(buffer.read(), 0.0);
+            assert_eq!(buffer.read_interp(1.5, Interpolation::Cubic), 0.0);
+            assert_eq!(buffer.read_ping_pong(1.5, Interpolation::Linear), 0.0);
+            assert_eq!(buffer.peek(0.0), 0.0);
+            assert_eq!(buffer.power(), 0.0);
+            assert_eq!(buffer.crossing_before(3, 3), None);
+        }
+    }
+
+    #[test]
+    fn smooth_softens_both_sides_of_the_seam() {
+        // A ramp jumps from 15 back down to 0 where the loop starts over.
+        let mut buffer = written(16, (0..16).map(f64::from));
+        buffer.smooth(4, PlayDirection::Forward);
+        let looped = read(&mut buffer, 16);
+        assert!(looped[0] > 0.0 && looped[15] < 15.0, "{looped:?}");
+        assert!((looped[0] - looped[15]).abs() <= 15.0 / 4.0, "{looped:?}");
+        // Leaving the samples further from it alone.
+        let middle: Vec<f64> = (4..12).map(f64::from).collect();
+        assert_eq!(looped[4..12], middle);
+    }
+
+    /// Largest error reading 64 samples of a sine with a period of 16 at
+    /// one and a half times the rate it was written at.
+    fn sine_error_at_one_and_a_half(interpolation: Interpolation) -> f64 {
+        use std::f64::consts::TAU;
+        let sine = |phase: f64| (TAU * phase / 16.0).sin();
+        let mut buffer = written(64, (0..64).map(|index| sine(index as f64)));
+        (0..128)
+            .map(|read| {
+                let phase = (1.5 * read as f64) % 64.0;
+                (buffer.read_interp(1.5, interpolation) - sine(phase)).abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn cubic_reads_reconstruct_a_sine_better_than_linear_ones() {
+        let linear = sine_error_at_one_and_a_half(Interpolation::Linear);
+        let cubic = sine_error_at_one_and_a_half(Interpolation::Cubic);
+        // Around 0.019 against 0.0005.
+        assert!(linear > 0.01, "{linear}");
+        assert!(cubic < linear / 10.0, "{cubic} vs {linear}");
+    }
+}
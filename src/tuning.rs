@@ -0,0 +1,113 @@
+//! Per-note tuning tables for scales which aren't 12-tone equal temperament.
+
+/// Number of MIDI notes.
+pub const NOTES: usize = 128;
+
+/// Exact frequency of every MIDI note.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TuningTable {
+    freqs: [f64; NOTES],
+}
+
+impl TuningTable {
+    /// Table from explicit per-note frequencies in Hz, all of which must be
+    /// positive and finite.
+    pub fn from_freqs(freqs: [f64; NOTES]) -> Option<TuningTable> {
+        freqs
+            .iter()
+            .all(|freq| freq.is_finite() && *freq > 0.0)
+            .then_some(TuningTable { freqs })
+    }
+
+    /// Table repeating a scale given in cents above its first degree, the
+    /// way Scala lists it: the last entry is the period, usually 1200 cents.
+    ///
+    /// `base_note` sounds at `base_freq` and the scale degrees are laid out
+    /// on consecutive keys from there.
+    pub fn from_cents(cents: &[f64], base_note: u8, base_freq: f64) -> Option<TuningTable> {
+        let period = *cents.last()?;
+        let degrees = cents.len() as i32;
+        let mut freqs = [0.0; NOTES];
+        for (note, freq) in freqs.iter_mut().enumerate() {
+            let offset = note as i32 - i32::from(base_note);
+            let (octave, degree) = (offset.div_euclid(degrees), offset.rem_euclid(degrees));
+            let degree_cents = match degree {
+                0 => 0.0,
+                degree => cents[degree as usize - 1],
+            };
+            let note_cents = f64::from(octave) * period + degree_cents;
+            *freq = base_freq * (note_cents / 1200.0).exp2();
+        }
+        TuningTable::from_freqs(freqs)
+    }
+
+    /// Table from the contents of a Scala `.scl` file, see `from_cents`.
+    pub fn from_scl(text: &str, base_note: u8, base_freq: f64) -> Option<TuningTable> {
+        // Lines starting with `!` are comments. The first remaining line is
+        // a description, followed by the number of degrees and then one
+        // pitch per line, of which only the first word counts.
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        let _description = lines.next()?;
+        let count: usize = lines.next()?.trim().parse().ok()?;
+        let cents = lines
+            .take(count)
+            .map(|line| parse_scl_pitch(line.split_whitespace().next()?))
+            .collect::<Option<Vec<_>>>()?;
+        if cents.len() != count {
+            return None;
+        }
+        TuningTable::from_cents(&cents, base_note, base_freq)
+    }
+
+    pub fn freq(&self, pitch: u8) -> f64 {
+        self.freqs[usize::from(pitch) % NOTES]
+    }
+
+    pub fn freqs(&self) -> &[f64; NOTES] {
+        &self.freqs
+    }
+}
+
+/// Pitch of a `.scl` degree in cents: values with a period are cents,
+/// others are ratios like `3/2` or plain integers like `2`.
+fn parse_scl_pitch(word: &str) -> Option<f64> {
+    if word.contains('.') {
+        return word.parse().ok();
+    }
+    let (numerator, denominator) = word.split_once('/').unwrap_or((word, "1"));
+    let ratio = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (ratio > 0.0).then(|| 1200.0 * ratio.log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIDDLE_C: f64 = 261.625_565_300_598_6;
+
+    fn assert_close(left: f64, right: f64) {
+        assert!((left - right).abs() < 1e-9, "{left} vs {right}");
+    }
+
+    #[test]
+    fn from_scl_reads_cents_and_ratios() {
+        let scl =
+            "! just.scl\n!\nJust major third and fifth\n 3\n!\n 386.3137 pure third\n 3/2\n 2\n";
+        let table = TuningTable::from_scl(scl, 60, MIDDLE_C).unwrap();
+        assert_close(table.freq(60), MIDDLE_C);
+        assert_close(table.freq(61), MIDDLE_C * (386.3137f64 / 1200.0).exp2());
+        assert_close(table.freq(62), MIDDLE_C * 1.5);
+        assert_close(table.freq(63), MIDDLE_C * 2.0);
+        // The scale repeats in its period either way.
+        assert_close(table.freq(65), MIDDLE_C * 3.0);
+        assert_close(table.freq(59), MIDDLE_C * 0.75);
+    }
+
+    #[test]
+    fn from_scl_rejects_a_count_not_matching_the_pitches() {
+        let short = "Too few\n 4\n 100.0\n 3/2\n 2/1\n";
+        assert_eq!(TuningTable::from_scl(short, 60, MIDDLE_C), None);
+        let garbled = "No count\n three\n 100.0\n 3/2\n 2/1\n";
+        assert_eq!(TuningTable::from_scl(garbled, 60, MIDDLE_C), None);
+    }
+}